@@ -0,0 +1,206 @@
+//! saves buffers without being asked.
+//!
+//! a buffer does not watch the clock itself. the editor loop calls
+//! `poll_autosave` whenever it is waiting for input, and `focus_lost` when
+//! it switches away from a buffer or loses focus, and the buffer saves
+//! itself if its policy says it is time. saves go through `save`, so they
+//! are as safe as any other. editing never saves, so a slow disk only holds
+//! up the editor when it is idle.
+use std::io;
+use std::time::{Duration, Instant};
+use crate::Buffer;
+use crate::disk::DiskChange;
+
+/// when a buffer saves itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Autosave {
+    Off,
+    /// once the text has not changed for this long
+    Idle(Duration),
+    /// once this many edits have been made since the last save
+    Interval(usize),
+    /// when the editor switches to another buffer or loses focus
+    OnSwitch,
+}
+
+impl Buffer {
+    /// the autosave policy
+    pub fn autosave(&self) -> Autosave {
+        self.autosave
+    }
+
+    pub fn set_autosave(&mut self, autosave: Autosave) {
+        self.autosave = autosave;
+    }
+
+    /// true if the buffer is never saved automatically. this is set when
+    /// the file loaded can't be written, and like vim's 'readonly' it does
+    /// not stop edits or an explicit save.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// saves the buffer if its policy says it is time at `now`. meant to be
+    /// called by the editor loop when no input is waiting, so saves happen
+    /// in the pauses between keys. returns true if the buffer was saved.
+    pub fn poll_autosave(&mut self, now: Instant) -> io::Result<bool> {
+        let due = match self.autosave {
+            Autosave::Idle(idle) => self.changed
+                .is_some_and(|changed| now.saturating_duration_since(changed) >= idle),
+            Autosave::Interval(n) => self.edits >= n.max(1),
+            Autosave::Off | Autosave::OnSwitch => false,
+        };
+        if due {
+            self.try_autosave()
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// saves the buffer if its policy is to save when the editor switches
+    /// away from it. returns true if the buffer was saved.
+    pub fn focus_lost(&mut self) -> io::Result<bool> {
+        if self.autosave == Autosave::OnSwitch {
+            self.try_autosave()
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// saves the buffer, unless there is nothing to save, nowhere to save
+    /// it, or saving could lose something.
+    fn try_autosave(&mut self) -> io::Result<bool> {
+        // a command that is halfway through its edits is left to finish,
+        // and changes made to the file by something else are left for the
+        // user to deal with
+        if self.filename.is_none() || self.read_only || !self.is_modified()
+            || self.group_depth > 0 || self.recovery.is_some()
+            || self.check_disk()? != DiskChange::Unchanged {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// counts a change to the text towards the next autosave
+    pub(crate) fn note_change(&mut self) {
+        self.edits += 1;
+        self.changed = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, Instant};
+    use crate::Buffer;
+    use crate::autosave::Autosave;
+    use crate::rope::Rope;
+    use crate::testutil::{load, temp_file};
+
+    #[test]
+    fn test_idle() {
+        let (_dir, path) = temp_file("autosave-idle", "abc");
+        let mut b = load(&path);
+        let second = Duration::from_secs(1);
+        b.set_autosave(Autosave::Idle(second));
+        assert!(!b.poll_autosave(Instant::now() + second).unwrap());
+
+        b.insert_at_cursor("x");
+        assert!(!b.poll_autosave(Instant::now()).unwrap());
+        assert!(b.poll_autosave(Instant::now() + second).unwrap());
+        assert!(!b.is_modified());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xabc");
+        assert!(!b.poll_autosave(Instant::now() + second).unwrap());
+    }
+
+    #[test]
+    fn test_interval() {
+        let (_dir, path) = temp_file("autosave-interval", "abc");
+        let mut b = load(&path);
+        b.set_autosave(Autosave::Interval(4));
+        b.insert_at_cursor("x");
+        b.insert_at_cursor("y");
+        b.undo();
+        assert!(!b.poll_autosave(Instant::now()).unwrap());
+        b.insert_at_cursor("z");
+        assert!(b.poll_autosave(Instant::now()).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "zabc");
+
+        // undoing to the saved text leaves nothing to save
+        b.set_autosave(Autosave::Interval(2));
+        b.insert_at_cursor("1");
+        b.undo();
+        assert!(!b.poll_autosave(Instant::now()).unwrap());
+    }
+
+    #[test]
+    fn test_edits_do_not_save() {
+        let (_dir, path) = temp_file("autosave-edits", "abc");
+        let mut b = load(&path);
+        b.set_autosave(Autosave::Interval(1));
+        b.insert_at_cursor("x");
+        b.insert_at_cursor("yz");
+        b.undo();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc");
+        assert!(b.poll_autosave(Instant::now()).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xabc");
+    }
+
+    #[test]
+    fn test_load_resets() {
+        let (_dir, path) = temp_file("autosave-load", "abc");
+        let mut b = load(&path);
+        b.set_autosave(Autosave::Interval(2));
+        b.insert_at_cursor("x");
+        b.insert_newline();
+        b.load_file(path.to_str().unwrap().to_string()).unwrap();
+        b.set_cursor(0);
+        b.insert_at_cursor("y");
+        assert!(!b.poll_autosave(Instant::now()).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_on_switch() {
+        let (_dir, path) = temp_file("autosave-switch", "abc");
+        let mut b = load(&path);
+        b.insert_at_cursor("x");
+        assert!(!b.focus_lost().unwrap());
+        b.set_autosave(Autosave::OnSwitch);
+        assert!(!b.poll_autosave(Instant::now() + Duration::from_secs(60)).unwrap());
+        assert!(b.focus_lost().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xabc");
+    }
+
+    #[test]
+    fn test_skipped() {
+        let mut b = Buffer::from(Rope::from(""));
+        b.set_autosave(Autosave::OnSwitch);
+        b.insert_at_cursor("x");
+        assert!(!b.focus_lost().unwrap());
+
+        let (_dir, path) = temp_file("autosave-skipped", "abc");
+        let mut b = load(&path);
+        assert!(!b.is_read_only());
+        b.set_autosave(Autosave::OnSwitch);
+        b.insert_at_cursor("x");
+        b.set_read_only(true);
+        assert!(!b.focus_lost().unwrap());
+        b.set_read_only(false);
+
+        // the file was changed by something else
+        fs::write(&path, "abcd").unwrap();
+        assert!(!b.focus_lost().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abcd");
+
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms).unwrap();
+        assert!(load(&path).is_read_only());
+    }
+}
@@ -0,0 +1,532 @@
+//! finds word, sentence and paragraph boundaries in a rope, for motions and
+//! text objects.
+use std::borrow::Cow;
+use std::ops::Range;
+use unicode_segmentation::{UnicodeSegmentation, GraphemeCursor, GraphemeIncomplete};
+use crate::rope::Rope;
+
+/// the direction to search for a boundary in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// what is considered to be a word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordKind {
+    /// a run of letters, digits and `_`, or a run of other non-blank
+    /// characters. like vim's `word`, but split with unicode word
+    /// segmentation (UAX #29) too, so each CJK ideograph is a word of its
+    /// own.
+    Word,
+    /// a run of non-blank characters. like vim's `WORD`.
+    BigWord,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CharClass {
+    Eol,
+    Space,
+    Word,
+    Punct,
+}
+
+/// the class of a grapheme, which is the class of its first character
+fn class(grapheme: &str, kind: WordKind) -> CharClass {
+    match grapheme.chars().next() {
+        Some('\n') => CharClass::Eol,
+        Some(ch) if ch.is_whitespace() => CharClass::Space,
+        _ if kind == WordKind::BigWord => CharClass::Word,
+        Some(ch) if ch.is_alphanumeric() || ch == '_' => CharClass::Word,
+        _ => CharClass::Punct,
+    }
+}
+
+/// true if segments of `class` next to each other make one unit. blanks
+/// and punctuation are grouped, but words from segmentation are not.
+fn joins(class: CharClass, kind: WordKind) -> bool {
+    match class {
+        CharClass::Eol => false,
+        CharClass::Word => kind == WordKind::BigWord,
+        CharClass::Space | CharClass::Punct => true,
+    }
+}
+
+fn starts_word(prev: Option<CharClass>, class: CharClass) -> bool {
+    match class {
+        CharClass::Space => false,
+        // empty lines count as words, like in vim
+        CharClass::Eol => matches!(prev, None | Some(CharClass::Eol)),
+        _ => true,
+    }
+}
+
+fn ends_word(class: CharClass) -> bool {
+    class == CharClass::Word || class == CharClass::Punct
+}
+
+/// characters that may come between the end of a sentence and the
+/// whitespace after it
+const SENTENCE_CLOSERS: &str = ")]\"'";
+
+impl Rope {
+    /// finds the end of the grapheme (user visible character) at `idx`.
+    /// returns the end of the rope if `idx` is at the end.
+    pub fn next_grapheme_boundary(&self, idx: usize) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        let mut cursor = GraphemeCursor::new(idx, self.len(), true);
+        let (mut leaf, mut start) = self.leaf_at(idx);
+        loop {
+            match cursor.next_boundary(leaf, start) {
+                Ok(b) => return b.unwrap_or_else(|| self.len()),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    let next = self.leaf_at(start + leaf.len());
+                    leaf = next.0;
+                    start = next.1;
+                },
+                Err(GraphemeIncomplete::PreContext(n)) => self.provide_context(&mut cursor, n),
+                Err(_) => return idx,
+            }
+        }
+    }
+
+    /// finds the start of the grapheme (user visible character) before
+    /// `idx`. returns 0 if `idx` is at the start of the rope.
+    pub fn prev_grapheme_boundary(&self, idx: usize) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        if idx == 0 {
+            return 0;
+        }
+        let mut cursor = GraphemeCursor::new(idx, self.len(), true);
+        let (mut leaf, mut start) = self.leaf_at(idx - 1);
+        loop {
+            match cursor.prev_boundary(leaf, start) {
+                Ok(b) => return b.unwrap_or(0),
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    let prev = self.leaf_at(start - 1);
+                    leaf = prev.0;
+                    start = prev.1;
+                },
+                Err(GraphemeIncomplete::PreContext(n)) => self.provide_context(&mut cursor, n),
+                Err(_) => return idx,
+            }
+        }
+    }
+
+    /// gives `cursor` the text before byte `n` that it asked for.
+    fn provide_context(&self, cursor: &mut GraphemeCursor, n: usize) {
+        let (leaf, start) = self.leaf_at(n - 1);
+        cursor.provide_context(&leaf[..n - start], start);
+    }
+
+    /// finds the start of the word after (forward) or before (backward)
+    /// `idx`, like vim's `w` and `b` motions. empty lines count as words.
+    /// returns the end or start of the rope if there are no more words.
+    pub fn word_start(&self, idx: usize, kind: WordKind, dir: Direction) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        match dir {
+            Direction::Forward => {
+                let start = self.unit_start(idx, kind);
+                let mut prev = self.units_before(start, kind).next().map(|u| u.1);
+                for (i, class) in self.units_from(start, kind) {
+                    if i > idx && starts_word(prev, class) {
+                        return i;
+                    }
+                    prev = Some(class);
+                }
+                self.len()
+            },
+            Direction::Backward => {
+                let mut units = self.units_before(idx, kind).peekable();
+                while let Some((i, class)) = units.next() {
+                    if starts_word(units.peek().map(|u| u.1), class) {
+                        return i;
+                    }
+                }
+                0
+            },
+        }
+    }
+
+    /// finds the end of the word after (forward) or before (backward) `idx`,
+    /// like vim's `e` and `ge` motions. the end is the offset just past the
+    /// last character of the word.
+    /// returns the end or start of the rope if there are no more words.
+    pub fn word_end(&self, idx: usize, kind: WordKind, dir: Direction) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        let start = self.unit_start(idx, kind);
+        match dir {
+            Direction::Forward => {
+                let mut units = self.units_from(start, kind).peekable();
+                while let Some((_, class)) = units.next() {
+                    let end = units.peek().map_or(self.len(), |u| u.0);
+                    if end > idx && ends_word(class) {
+                        return end;
+                    }
+                }
+                self.len()
+            },
+            Direction::Backward => {
+                let mut next = self.units_from(start, kind).next();
+                for (i, class) in self.units_before(start, kind) {
+                    let end = next.map_or(self.len(), |u| u.0);
+                    if end < idx && ends_word(class) {
+                        return end;
+                    }
+                    next = Some((i, class));
+                }
+                0
+            },
+        }
+    }
+
+    /// finds the word or run of blanks at `idx`, like vim's `iw` text
+    /// object. a newline is a run of its own.
+    pub fn word_at(&self, idx: usize, kind: WordKind) -> Range<usize> {
+        let start = self.unit_start(idx, kind);
+        let mut units = self.units_from(start, kind);
+        if units.next().is_none() {
+            return start..start;
+        }
+        start..units.next().map_or(self.len(), |u| u.0)
+    }
+
+    /// finds the start of the sentence after (forward) or before (backward)
+    /// `idx`, like vim's `)` and `(` motions.
+    ///
+    /// a sentence ends with `.`, `!` or `?`, optionally followed by closing
+    /// brackets or quotes, and then whitespace. the first character of a
+    /// paragraph and empty lines also start sentences.
+    pub fn sentence_start(&self, idx: usize, dir: Direction) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        match dir {
+            Direction::Forward => self.chars_from(idx)
+                .map(|(i, _)| i)
+                .find(|&i| i > idx && self.is_sentence_start(i))
+                .unwrap_or_else(|| self.len()),
+            Direction::Backward => self.chars_before(idx)
+                .map(|(i, _)| i)
+                .find(|&i| self.is_sentence_start(i))
+                .unwrap_or(0),
+        }
+    }
+
+    /// finds the start of the blank line after (forward) or before
+    /// (backward) the paragraph at `idx`, like vim's `}` and `{` motions.
+    /// a blank line is empty or contains only whitespace.
+    /// returns the end or start of the rope if there is no such line.
+    pub fn paragraph_start(&self, idx: usize, dir: Direction) -> usize {
+        let last = self.lenlines();
+        let mut l = self.line_at(idx);
+        match dir {
+            Direction::Forward => {
+                while l <= last && self.is_blank_line(l) {
+                    l += 1;
+                }
+                while l <= last && !self.is_blank_line(l) {
+                    l += 1;
+                }
+                self.line_start(l)
+            },
+            Direction::Backward => {
+                while self.is_blank_line(l) {
+                    if l == 0 {
+                        return 0;
+                    }
+                    l -= 1;
+                }
+                while !self.is_blank_line(l) {
+                    if l == 0 {
+                        return 0;
+                    }
+                    l -= 1;
+                }
+                self.line_start(l)
+            },
+        }
+    }
+
+    /// finds the lines around `idx` that are all blank or all non-blank,
+    /// like vim's `ip` text object. the range includes the final newline.
+    pub fn paragraph_at(&self, idx: usize) -> Range<usize> {
+        let last = self.lenlines();
+        let l = self.line_at(idx);
+        let blank = self.is_blank_line(l);
+
+        let mut first = l;
+        while first > 0 && self.is_blank_line(first - 1) == blank {
+            first -= 1;
+        }
+        let mut end = l;
+        while end < last && self.is_blank_line(end + 1) == blank {
+            end += 1;
+        }
+        self.line_start(first)..self.line_start(end + 1)
+    }
+
+    /// the units of line `lnum` with their classes: the segments of unicode
+    /// word segmentation, split where a word character meets another, with
+    /// blanks and punctuation next to each other grouped, and then the
+    /// newline.
+    fn line_units(&self, lnum: usize, kind: WordKind) -> Vec<(usize, CharClass)> {
+        let start = self.line_start(lnum);
+        let end = self.line_start(lnum + 1);
+        // a line is usually all in one leaf, and only copied if it is not
+        let (leaf, at) = self.leaf_at(start);
+        let line: Cow<str> = if end <= at + leaf.len() {
+            Cow::Borrowed(&leaf[start - at..end - at])
+        } else {
+            Cow::Owned(self.char_slice(start..end).str_iter().collect())
+        };
+        let text = line.strip_suffix('\n').unwrap_or(&line);
+        let mut units: Vec<(usize, CharClass)> = vec![];
+        for (i, seg) in text.split_word_bound_indices() {
+            for (j, grapheme) in seg.grapheme_indices(true) {
+                let class = class(grapheme, kind);
+                // word characters only join others in the same segment
+                let joined = units.last()
+                    .is_some_and(|&(_, last)| last == class && (j > 0 || joins(class, kind)));
+                if !joined {
+                    units.push((start + i + j, class));
+                }
+            }
+        }
+        if text.len() < line.len() {
+            units.push((start + text.len(), CharClass::Eol));
+        }
+        units
+    }
+
+    /// the units from the one starting at `start` onwards.
+    fn units_from(&self, start: usize, kind: WordKind)
+        -> impl Iterator<Item = (usize, CharClass)> + '_ {
+        (self.line_at(start)..=self.lenlines())
+            .flat_map(move |l| self.line_units(l, kind))
+            .skip_while(move |u| u.0 < start)
+    }
+
+    /// the units that start before `idx`, in reverse.
+    fn units_before(&self, idx: usize, kind: WordKind)
+        -> impl Iterator<Item = (usize, CharClass)> + '_ {
+        (0..=self.line_at(idx)).rev()
+            .flat_map(move |l| self.line_units(l, kind).into_iter().rev())
+            .skip_while(move |u| u.0 >= idx)
+    }
+
+    /// moves `idx` back to the start of the unit it is in the middle of.
+    fn unit_start(&self, idx: usize, kind: WordKind) -> usize {
+        let idx = self.floor_char_boundary(idx);
+        if idx >= self.len() {
+            return idx;
+        }
+        self.line_units(self.line_at(idx), kind)
+            .into_iter()
+            .map(|u| u.0)
+            .take_while(|&i| i <= idx)
+            .last()
+            .unwrap_or(idx)
+    }
+
+    fn is_sentence_start(&self, idx: usize) -> bool {
+        let ch = match self.chars_from(idx).next() {
+            Some((_, ch)) => ch,
+            None => return false,
+        };
+        let mut before = self.chars_before(idx).map(|(_, ch)| ch).peekable();
+        if ch == '\n' {
+            return matches!(before.peek(), None | Some('\n'));
+        } else if ch.is_whitespace() {
+            return false;
+        }
+
+        let mut spaced = false;
+        let mut newlines = 0;
+        while let Some(&c) = before.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            spaced = true;
+            if c == '\n' {
+                newlines += 1;
+            }
+            before.next();
+        }
+        if before.peek().is_none() || newlines >= 2 {
+            // the first character of the text or of a paragraph
+            return true;
+        } else if !spaced {
+            return false;
+        }
+
+        while before.next_if(|&c| SENTENCE_CLOSERS.contains(c)).is_some() {}
+        matches!(before.next(), Some('.') | Some('!') | Some('?'))
+    }
+
+    fn is_blank_line(&self, lnum: usize) -> bool {
+        self.line_slice(lnum..=lnum).char_iter().all(char::is_whitespace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rope::Rope;
+    use crate::boundary::{Direction, WordKind};
+
+    fn leaves(parts: &[&str]) -> Rope {
+        parts.iter().fold(Rope::from(""), |r, s| Rope::concat(&r, &Rope::from(*s)))
+    }
+
+    /// repeatedly applies a motion from the start until it stops moving
+    fn walk(mut f: impl FnMut(usize) -> usize) -> Vec<usize> {
+        let mut idx = 0;
+        let mut stops = vec![];
+        loop {
+            let next = f(idx);
+            if next == idx {
+                return stops;
+            }
+            stops.push(next);
+            idx = next;
+        }
+    }
+
+    #[test]
+    fn test_grapheme_boundary() {
+        let r = leaves(&["ae\u{301}", "\u{302}x\r", "\n🇨", "🇦"]);
+        assert_eq!(r.next_grapheme_boundary(0), 1);
+        assert_eq!(r.next_grapheme_boundary(1), 6);
+        assert_eq!(r.next_grapheme_boundary(2), 6);
+        assert_eq!(r.next_grapheme_boundary(6), 7);
+        assert_eq!(r.next_grapheme_boundary(7), 9);
+        assert_eq!(r.next_grapheme_boundary(9), 17);
+        assert_eq!(r.next_grapheme_boundary(17), 17);
+
+        assert_eq!(r.prev_grapheme_boundary(17), 9);
+        assert_eq!(r.prev_grapheme_boundary(9), 7);
+        assert_eq!(r.prev_grapheme_boundary(7), 6);
+        assert_eq!(r.prev_grapheme_boundary(6), 1);
+        assert_eq!(r.prev_grapheme_boundary(1), 0);
+        assert_eq!(r.prev_grapheme_boundary(0), 0);
+        assert_eq!(Rope::from("").next_grapheme_boundary(0), 0);
+    }
+
+    #[test]
+    fn test_word_start() {
+        let r = leaves(&["foo.b", "ar(baz) ", " qu", "x\n\n  end"]);
+        let fwd = walk(|i| r.word_start(i, WordKind::Word, Direction::Forward));
+        assert_eq!(fwd, vec![3, 4, 7, 8, 11, 14, 18, 21, 24]);
+        let big = walk(|i| r.word_start(i, WordKind::BigWord, Direction::Forward));
+        assert_eq!(big, vec![14, 18, 21, 24]);
+
+        assert_eq!(r.word_start(24, WordKind::Word, Direction::Backward), 21);
+        assert_eq!(r.word_start(21, WordKind::Word, Direction::Backward), 18);
+        assert_eq!(r.word_start(18, WordKind::Word, Direction::Backward), 14);
+        assert_eq!(r.word_start(13, WordKind::Word, Direction::Backward), 11);
+        assert_eq!(r.word_start(11, WordKind::Word, Direction::Backward), 8);
+        assert_eq!(r.word_start(14, WordKind::BigWord, Direction::Backward), 0);
+        assert_eq!(r.word_start(0, WordKind::Word, Direction::Backward), 0);
+    }
+
+    #[test]
+    fn test_word_end() {
+        let r = leaves(&["foo.b", "ar(baz) ", " qu", "x\n\n  end"]);
+        let fwd = walk(|i| r.word_end(i, WordKind::Word, Direction::Forward));
+        assert_eq!(fwd, vec![3, 4, 7, 8, 11, 12, 17, 24]);
+        let big = walk(|i| r.word_end(i, WordKind::BigWord, Direction::Forward));
+        assert_eq!(big, vec![12, 17, 24]);
+
+        assert_eq!(r.word_end(24, WordKind::Word, Direction::Backward), 17);
+        assert_eq!(r.word_end(17, WordKind::Word, Direction::Backward), 12);
+        assert_eq!(r.word_end(14, WordKind::Word, Direction::Backward), 12);
+        assert_eq!(r.word_end(5, WordKind::Word, Direction::Backward), 4);
+        assert_eq!(r.word_end(3, WordKind::Word, Direction::Backward), 0);
+    }
+
+    #[test]
+    fn test_word_unicode() {
+        // combining accents, other scripts and wide characters
+        let r = leaves(&["cafe\u{301} naï", "ve-日本語", " x"]);
+        assert_eq!(r.word_start(0, WordKind::Word, Direction::Forward), 7);
+        assert_eq!(r.word_end(0, WordKind::Word, Direction::Forward), 6);
+        assert_eq!(r.word_end(7, WordKind::Word, Direction::Forward), 13);
+        assert_eq!(r.word_start(7, WordKind::Word, Direction::Forward), 13);
+        assert_eq!(r.word_start(13, WordKind::Word, Direction::Forward), 14);
+        // each ideograph is a word
+        assert_eq!(r.word_start(14, WordKind::Word, Direction::Forward), 17);
+        assert_eq!(r.word_start(20, WordKind::Word, Direction::Forward), 24);
+        assert_eq!(r.word_start(24, WordKind::Word, Direction::Backward), 20);
+        assert_eq!(r.word_start(24, WordKind::BigWord, Direction::Backward), 7);
+        // starting on the accent is the same as starting on its letter
+        assert_eq!(r.word_at(4, WordKind::Word), 0..6);
+        assert_eq!(r.word_start(4, WordKind::Word, Direction::Backward), 0);
+    }
+
+    #[test]
+    fn test_word_segmentation() {
+        // apostrophes and dots split words, as in vim
+        let r = leaves(&["can't st", "op 3.14 e.g.", " a_b ==>\r\n"]);
+        let fwd = walk(|i| r.word_start(i, WordKind::Word, Direction::Forward));
+        assert_eq!(fwd, vec![3, 4, 6, 11, 12, 13, 16, 17, 18, 19, 21, 25, 30]);
+        assert_eq!(r.word_at(9, WordKind::Word), 6..10);
+        assert_eq!(r.word_at(13, WordKind::Word), 13..15);
+        assert_eq!(r.word_at(19, WordKind::Word), 19..20);
+        assert_eq!(r.word_at(22, WordKind::Word), 21..24);
+        assert_eq!(r.word_at(26, WordKind::Word), 25..28);
+
+        let r = Rope::from("self.foo = obj.bar;\n");
+        assert_eq!(r.word_at(1, WordKind::Word), 0..4);
+        assert_eq!(r.word_at(4, WordKind::Word), 4..5);
+        assert_eq!(r.word_at(16, WordKind::Word), 15..18);
+        assert_eq!(r.word_at(16, WordKind::BigWord), 11..19);
+    }
+
+    #[test]
+    fn test_word_at() {
+        let r = leaves(&["foo.b", "ar(baz)  ", "qux\n"]);
+        assert_eq!(r.word_at(1, WordKind::Word), 0..3);
+        assert_eq!(r.word_at(3, WordKind::Word), 3..4);
+        assert_eq!(r.word_at(5, WordKind::Word), 4..7);
+        assert_eq!(r.word_at(5, WordKind::BigWord), 0..12);
+        assert_eq!(r.word_at(13, WordKind::Word), 12..14);
+        assert_eq!(r.word_at(17, WordKind::Word), 17..18);
+        assert_eq!(r.word_at(18, WordKind::Word), 18..18);
+    }
+
+    #[test]
+    fn test_sentence_start() {
+        let r = leaves(&["One two. Thr", "ee? (Four.) \"Fi", "ve\" e.g. x\n\nSix"]);
+        let fwd = walk(|i| r.sentence_start(i, Direction::Forward));
+        assert_eq!(fwd, vec![9, 16, 24, 36, 38, 39, 42]);
+
+        assert_eq!(r.sentence_start(42, Direction::Backward), 39);
+        assert_eq!(r.sentence_start(39, Direction::Backward), 38);
+        assert_eq!(r.sentence_start(38, Direction::Backward), 36);
+        assert_eq!(r.sentence_start(20, Direction::Backward), 16);
+        assert_eq!(r.sentence_start(9, Direction::Backward), 0);
+    }
+
+    #[test]
+    fn test_paragraph_start() {
+        let r = leaves(&["a\nb\n\n", "  \nc\nd", "\n\n\ne\n"]);
+        let fwd = walk(|i| r.paragraph_start(i, Direction::Forward));
+        assert_eq!(fwd, vec![4, 12, 16]);
+
+        assert_eq!(r.paragraph_start(16, Direction::Backward), 13);
+        assert_eq!(r.paragraph_start(13, Direction::Backward), 5);
+        assert_eq!(r.paragraph_start(10, Direction::Backward), 5);
+        assert_eq!(r.paragraph_start(5, Direction::Backward), 0);
+        assert_eq!(r.paragraph_start(4, Direction::Backward), 0);
+    }
+
+    #[test]
+    fn test_paragraph_at() {
+        let r = leaves(&["a\nb\n\n", "  \nc\nd", "\n\n\ne\n"]);
+        assert_eq!(r.paragraph_at(0), 0..4);
+        assert_eq!(r.paragraph_at(3), 0..4);
+        assert_eq!(r.paragraph_at(4), 4..8);
+        assert_eq!(r.paragraph_at(9), 8..12);
+        assert_eq!(r.paragraph_at(12), 12..14);
+        assert_eq!(r.paragraph_at(14), 14..16);
+    }
+}
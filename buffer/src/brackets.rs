@@ -0,0 +1,234 @@
+//! finds matching brackets and quotes in a rope, for `%` and text objects
+//! like `ci(`.
+use std::ops::Range;
+use crate::boundary::Direction;
+use crate::rope::Rope;
+
+/// the pairs of brackets that can be matched
+pub const BRACKETS: [(char, char); 4] = [('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')];
+
+/// the quotes that can be matched. a quote is paired with the next one on
+/// the same line, ignoring quotes escaped with a `\`.
+pub const QUOTES: [char; 3] = ['"', '\'', '`'];
+
+/// decides which characters a search looks at, given the ranges the caller
+/// wants skipped. a search that starts inside a skipped range only looks
+/// inside of that range.
+struct Skipper<'a> {
+    ranges: &'a [Range<usize>],
+    within: Option<Range<usize>>,
+}
+
+impl<'a> Skipper<'a> {
+    fn new(ranges: &'a [Range<usize>], idx: usize) -> Self {
+        let within = Skipper::find(ranges, idx).cloned();
+        Skipper { ranges, within }
+    }
+
+    /// the range containing `idx`. `ranges` are sorted and do not overlap.
+    fn find(ranges: &[Range<usize>], idx: usize) -> Option<&Range<usize>> {
+        let i = ranges.partition_point(|r| r.end <= idx);
+        ranges.get(i).filter(|r| r.start <= idx)
+    }
+
+    /// true if the search has left the range it started in
+    fn escaped(&self, idx: usize) -> bool {
+        self.within.as_ref().is_some_and(|w| !w.contains(&idx))
+    }
+
+    fn skips(&self, idx: usize) -> bool {
+        self.within.is_none() && Skipper::find(self.ranges, idx).is_some()
+    }
+}
+
+impl Rope {
+    /// finds the bracket or quote that matches the one at `idx`, like vim's
+    /// `%` motion. returns None if there is no bracket or quote at `idx`, or
+    /// it has no match.
+    pub fn matching_bracket(&self, idx: usize) -> Option<usize> {
+        self.matching_bracket_skipping(idx, &[])
+    }
+
+    /// same as matching_bracket, but ignores the characters in `skip`, which
+    /// must be sorted and not overlap. this is meant for strings and comments.
+    pub fn matching_bracket_skipping(&self, idx: usize, skip: &[Range<usize>]) -> Option<usize> {
+        let skipper = Skipper::new(skip, idx);
+        let (_, ch) = self.chars_from(idx).next().filter(|&(i, _)| i == idx)?;
+
+        if QUOTES.contains(&ch) {
+            return self.quote_pairs(idx, ch, &skipper)
+                .into_iter()
+                .find(|&(o, c)| o == idx || c == idx)
+                .map(|(o, c)| if o == idx { c } else { o });
+        }
+        for &(open, close) in BRACKETS.iter() {
+            if ch == open {
+                return self.scan_unmatched(idx + 1, (open, close), Direction::Forward, &skipper);
+            } else if ch == close {
+                return self.scan_unmatched(idx, (open, close), Direction::Backward, &skipper);
+            }
+        }
+        None
+    }
+
+    /// finds the closest pair of `open` and `close` that contains `r`, like
+    /// vim's `a(` text object. the result includes both brackets, and is
+    /// always larger than `r`, so repeated calls select outer pairs.
+    /// `open` and `close` may be the same quote character.
+    pub fn enclosing_pair(&self, r: Range<usize>, pair: (char, char)) -> Option<Range<usize>> {
+        self.enclosing_pair_skipping(r, pair, &[])
+    }
+
+    /// same as enclosing_pair, but ignores the characters in `skip`, which
+    /// must be sorted and not overlap. this is meant for strings and comments.
+    pub fn enclosing_pair_skipping(&self, r: Range<usize>, (open, close): (char, char),
+                                   skip: &[Range<usize>]) -> Option<Range<usize>> {
+        let skipper = Skipper::new(skip, r.start);
+        let encloses = |o: usize, c: usize| {
+            let pair = o..c + close.len_utf8();
+            if pair.start <= r.start && pair.end >= r.end && pair != r {
+                Some(pair)
+            } else {
+                None
+            }
+        };
+
+        if open == close {
+            return self.quote_pairs(r.start, open, &skipper)
+                .into_iter()
+                .find_map(|(o, c)| encloses(o, c));
+        }
+
+        // a bracket right at the start of the range is part of the pair
+        let mut o = match self.chars_from(r.start).next() {
+            Some((i, ch)) if i == r.start && ch == open && !skipper.skips(i) => i,
+            _ => self.scan_unmatched(r.start, (open, close), Direction::Backward, &skipper)?,
+        };
+        loop {
+            let c = self.scan_unmatched(o + open.len_utf8(), (open, close),
+                                        Direction::Forward, &skipper)?;
+            if let Some(pair) = encloses(o, c) {
+                return Some(pair);
+            }
+            o = self.scan_unmatched(o, (open, close), Direction::Backward, &skipper)?;
+        }
+    }
+
+    /// scans from `idx` for a bracket that closes (forward) or opens
+    /// (backward) the text between it and `idx`.
+    fn scan_unmatched(&self, idx: usize, (open, close): (char, char), dir: Direction,
+                      skipper: &Skipper) -> Option<usize> {
+        let (chars, inc, dec): (Box<dyn Iterator<Item = (usize, char)>>, _, _) = match dir {
+            Direction::Forward => (Box::new(self.chars_from(idx)), open, close),
+            Direction::Backward => (Box::new(self.chars_before(idx)), close, open),
+        };
+
+        let mut depth = 0;
+        for (i, ch) in chars {
+            if skipper.escaped(i) {
+                return None;
+            } else if skipper.skips(i) {
+                continue;
+            } else if ch == inc {
+                depth += 1;
+            } else if ch == dec {
+                if depth == 0 {
+                    return Some(i);
+                }
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    /// pairs up the unescaped `quote`s on the line containing `idx`.
+    fn quote_pairs(&self, idx: usize, quote: char, skipper: &Skipper) -> Vec<(usize, usize)> {
+        let line = self.line_at(idx);
+        let end = self.line_start(line + 1);
+
+        let mut quotes = vec![];
+        let mut escaped = false;
+        for (i, ch) in self.chars_from(self.line_start(line)).take_while(|&(i, _)| i < end) {
+            if ch == quote && !escaped && !skipper.escaped(i) && !skipper.skips(i) {
+                quotes.push(i);
+            }
+            escaped = ch == '\\' && !escaped;
+        }
+        quotes.chunks_exact(2).map(|p| (p[0], p[1])).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rope::Rope;
+
+    fn leaves(parts: &[&str]) -> Rope {
+        parts.iter().fold(Rope::from(""), |r, s| Rope::concat(&r, &Rope::from(*s)))
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let r = leaves(&["f(a[1], {", "x: <T>}", ")\n"]);
+        assert_eq!(r.matching_bracket(1), Some(16));
+        assert_eq!(r.matching_bracket(16), Some(1));
+        assert_eq!(r.matching_bracket(3), Some(5));
+        assert_eq!(r.matching_bracket(8), Some(15));
+        assert_eq!(r.matching_bracket(15), Some(8));
+        assert_eq!(r.matching_bracket(12), Some(14));
+        assert_eq!(r.matching_bracket(0), None);
+        assert_eq!(r.matching_bracket(100), None);
+        assert_eq!(leaves(&["((", ")"]).matching_bracket(0), None);
+    }
+
+    #[test]
+    fn test_matching_quote() {
+        let r = leaves(&["s = \"a\\\"b", "\" + 'c'\n\"x"]);
+        assert_eq!(r.matching_bracket(4), Some(9));
+        assert_eq!(r.matching_bracket(9), Some(4));
+        assert_eq!(r.matching_bracket(7), None);
+        assert_eq!(r.matching_bracket(13), Some(15));
+        // quotes are not paired across lines
+        assert_eq!(r.matching_bracket(17), None);
+    }
+
+    #[test]
+    fn test_matching_bracket_skipping() {
+        let r = leaves(&["f(\")\", g(", "x)) // )"]);
+        let skip = [2..5, 13..17];
+        assert_eq!(r.matching_bracket(1), Some(3));
+        assert_eq!(r.matching_bracket_skipping(1, &skip), Some(11));
+        assert_eq!(r.matching_bracket_skipping(11, &skip), Some(1));
+        assert_eq!(r.matching_bracket_skipping(8, &skip), Some(10));
+        // starting inside a skipped range only searches inside of it
+        assert_eq!(r.matching_bracket_skipping(16, &skip), None);
+        assert_eq!(r.matching_bracket_skipping(3, &skip), None);
+    }
+
+    #[test]
+    fn test_enclosing_pair() {
+        let r = leaves(&["a(b(c", ", d)", ", e)f"]);
+        assert_eq!(r.enclosing_pair(4..4, ('(', ')')), Some(3..9));
+        assert_eq!(r.enclosing_pair(3..9, ('(', ')')), Some(1..13));
+        assert_eq!(r.enclosing_pair(1..13, ('(', ')')), None);
+        assert_eq!(r.enclosing_pair(10..11, ('(', ')')), Some(1..13));
+        // on a bracket selects its pair
+        assert_eq!(r.enclosing_pair(3..3, ('(', ')')), Some(3..9));
+        assert_eq!(r.enclosing_pair(8..8, ('(', ')')), Some(3..9));
+        assert_eq!(r.enclosing_pair(4..4, ('[', ']')), None);
+    }
+
+    #[test]
+    fn test_enclosing_pair_skipping() {
+        let r = leaves(&["{ \"}\" ", "x } // }"]);
+        assert_eq!(r.enclosing_pair(7..7, ('{', '}')), None);
+        assert_eq!(r.enclosing_pair_skipping(7..7, ('{', '}'), &[2..5, 10..14]), Some(0..9));
+    }
+
+    #[test]
+    fn test_enclosing_quote() {
+        let r = leaves(&["x = \"ab", "c\" + \"d\""]);
+        assert_eq!(r.enclosing_pair(5..6, ('"', '"')), Some(4..9));
+        assert_eq!(r.enclosing_pair(13..13, ('"', '"')), Some(12..15));
+        assert_eq!(r.enclosing_pair(10..10, ('"', '"')), None);
+    }
+}
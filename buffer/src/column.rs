@@ -0,0 +1,112 @@
+//! maps between byte offsets and the columns text is displayed in.
+use unicode_width::UnicodeWidthChar;
+use crate::rope::Rope;
+
+/// the tab width used when nothing else is configured
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// the number of columns `ch` takes up on screen when it starts at display
+/// column `col`. tabs extend to the next multiple of `tab_width`, wide east
+/// asian characters take 2 columns, and combining marks and control
+/// characters take none.
+pub fn char_width(ch: char, col: usize, tab_width: usize) -> usize {
+    if ch == '\t' {
+        let tab_width = tab_width.max(1);
+        tab_width - col % tab_width
+    } else {
+        ch.width().unwrap_or(0)
+    }
+}
+
+/// the number of columns `s` takes up on screen when it starts at display
+/// column `col`.
+pub fn str_width(s: &str, col: usize, tab_width: usize) -> usize {
+    s.chars().fold(col, |c, ch| c + char_width(ch, c, tab_width)) - col
+}
+
+impl Rope {
+    /// finds the display column of byte `idx`, counted from the start of its
+    /// line.
+    pub fn column_at(&self, idx: usize, tab_width: usize) -> usize {
+        let start = self.line_start(self.line_at(idx));
+        self.chars_from(start)
+            .take_while(|&(i, _)| i < idx)
+            .fold(0, |col, (_, ch)| col + char_width(ch, col, tab_width))
+    }
+
+    /// finds the byte offset of the character on line `lnum` that covers
+    /// display column `col`. a column in the middle of a tab or a wide
+    /// character gives the start of that character, and columns past the end
+    /// of the line give the end of the line, before its newline.
+    pub fn byte_at_column(&self, lnum: usize, col: usize, tab_width: usize) -> usize {
+        let mut c = 0;
+        for (i, ch) in self.chars_from(self.line_start(lnum)) {
+            if ch == '\n' {
+                return i;
+            }
+            let width = char_width(ch, c, tab_width);
+            if c + width > col {
+                return i;
+            }
+            c += width;
+        }
+        self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rope::Rope;
+    use crate::column::{char_width, str_width};
+
+    #[test]
+    fn test_char_width() {
+        assert_eq!(char_width('a', 0, 4), 1);
+        assert_eq!(char_width('日', 0, 4), 2);
+        assert_eq!(char_width('\u{301}', 3, 4), 0);
+        assert_eq!(char_width('\t', 0, 4), 4);
+        assert_eq!(char_width('\t', 5, 4), 3);
+        assert_eq!(char_width('\t', 8, 8), 8);
+        assert_eq!(char_width('\t', 3, 0), 1);
+    }
+
+    #[test]
+    fn test_str_width() {
+        assert_eq!(str_width("a\tb", 0, 4), 5);
+        assert_eq!(str_width("a\tb", 2, 4), 3);
+        assert_eq!(str_width("日本e\u{301}", 0, 4), 5);
+        assert_eq!(str_width("", 3, 4), 0);
+    }
+
+    #[test]
+    fn test_column_at() {
+        let r = Rope::concat(&Rope::from("x\n\tab日"), &Rope::from("本e\u{301}!\n"));
+        assert_eq!(r.column_at(0, 4), 0);
+        assert_eq!(r.column_at(1, 4), 1);
+        assert_eq!(r.column_at(2, 4), 0);
+        assert_eq!(r.column_at(3, 4), 4);
+        assert_eq!(r.column_at(3, 8), 8);
+        assert_eq!(r.column_at(5, 4), 6);
+        assert_eq!(r.column_at(8, 4), 8);
+        assert_eq!(r.column_at(11, 4), 10);
+        assert_eq!(r.column_at(14, 4), 11);
+        assert_eq!(r.column_at(15, 4), 12);
+    }
+
+    #[test]
+    fn test_byte_at_column() {
+        let r = Rope::concat(&Rope::from("x\n\tab日"), &Rope::from("本e\u{301}!\n"));
+        assert_eq!(r.byte_at_column(0, 0, 4), 0);
+        assert_eq!(r.byte_at_column(0, 5, 4), 1);
+        assert_eq!(r.byte_at_column(1, 0, 4), 2);
+        assert_eq!(r.byte_at_column(1, 3, 4), 2);
+        assert_eq!(r.byte_at_column(1, 4, 4), 3);
+        assert_eq!(r.byte_at_column(1, 7, 4), 5);
+        assert_eq!(r.byte_at_column(1, 9, 4), 8);
+        assert_eq!(r.byte_at_column(1, 10, 4), 11);
+        assert_eq!(r.byte_at_column(1, 11, 4), 14);
+        assert_eq!(r.byte_at_column(1, 40, 4), 15);
+        assert_eq!(r.byte_at_column(2, 3, 4), 16);
+        assert_eq!(r.byte_at_column(9, 3, 4), 16);
+    }
+}
@@ -0,0 +1,331 @@
+//! cursor motions on a buffer.
+//!
+//! the cursor sits between characters, so it can be anywhere from the start
+//! of the text to its end, including the end of a line just before its
+//! newline. motions always leave it on a grapheme boundary.
+use crate::Buffer;
+use crate::boundary::{Direction, WordKind};
+
+impl Buffer {
+    /// the byte offset of the cursor in the text
+    pub fn cursor(&self) -> usize {
+        self.text().line_start(self.line) + self.offset
+    }
+
+    /// the line the cursor is on
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// the byte offset of the cursor from the start of its line
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// the display column of the cursor
+    pub fn column(&self) -> usize {
+        self.text().column_at(self.cursor(), self.tab_width)
+    }
+
+    /// moves the cursor to byte `idx`, dropping any other selections.
+    /// positions past the end of the text or inside of a grapheme are moved
+    /// back to the closest grapheme boundary.
+    pub fn set_cursor(&mut self, idx: usize) {
+        self.others.clear();
+        self.move_cursor(idx);
+    }
+
+    /// moves the cursor back one grapheme, to the end of the previous line
+    /// if it is at the start of a line.
+    pub fn move_left(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.text().prev_grapheme_boundary(b.cursor());
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor forward one grapheme, to the start of the next line
+    /// if it is at the end of a line.
+    pub fn move_right(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.text().next_grapheme_boundary(b.cursor());
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor up a line, keeping the display column it had before
+    /// the first of a run of vertical motions where possible.
+    pub fn move_up(&mut self) {
+        self.page_up(1);
+    }
+
+    /// moves the cursor down a line, keeping the display column it had
+    /// before the first of a run of vertical motions where possible.
+    pub fn move_down(&mut self) {
+        self.page_down(1);
+    }
+
+    /// moves the cursor up `rows` lines, like move_up.
+    pub fn page_up(&mut self, rows: usize) {
+        self.each_selection(|b| b.move_to_line(b.line.saturating_sub(rows)));
+    }
+
+    /// moves the cursor down `rows` lines, like move_down.
+    pub fn page_down(&mut self, rows: usize) {
+        self.each_selection(|b| b.move_to_line(b.line.saturating_add(rows)));
+    }
+
+    /// moves the cursor to the start of the next word, like vim's `w`.
+    pub fn move_word_forward(&mut self, kind: WordKind) {
+        self.each_selection(|b| {
+            let idx = b.text().word_start(b.cursor(), kind, Direction::Forward);
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor to the start of the previous word, like vim's `b`.
+    pub fn move_word_backward(&mut self, kind: WordKind) {
+        self.each_selection(|b| {
+            let idx = b.text().word_start(b.cursor(), kind, Direction::Backward);
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor onto the last character of the word it is in, or
+    /// of the next word if it is already there, like vim's `e`.
+    pub fn move_word_end(&mut self, kind: WordKind) {
+        self.each_selection(|b| {
+            let text = b.text();
+            let from = text.next_grapheme_boundary(b.cursor());
+            let end = text.word_end(from, kind, Direction::Forward);
+            let idx = text.prev_grapheme_boundary(end);
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor to the start of its line.
+    pub fn move_line_start(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.text().line_start(b.line);
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor to the end of its line, before the newline.
+    /// following vertical motions stay at the end of each line.
+    pub fn move_line_end(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.text().line_end(b.line);
+            b.move_cursor(idx);
+            b.goal_col = Some(usize::MAX);
+        });
+    }
+
+    /// moves the cursor to the first character of its line that is not a
+    /// space or tab, like vim's `^`.
+    pub fn move_first_non_blank(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.first_non_blank(b.line);
+            b.move_cursor(idx);
+        });
+    }
+
+    /// moves the cursor to the first non-blank character of line `lnum`, or
+    /// of the last line if there are not that many. this is a jump, see the
+    /// marks module.
+    pub fn goto_line(&mut self, lnum: usize) {
+        self.record_jump();
+        let lnum = lnum.min(self.text().lenlines());
+        let idx = self.first_non_blank(lnum);
+        self.set_cursor(idx);
+    }
+
+    /// moves the cursor to the start of the text, as a jump.
+    pub fn move_buffer_start(&mut self) {
+        self.record_jump();
+        self.set_cursor(0);
+    }
+
+    /// moves the cursor to the end of the text, as a jump.
+    pub fn move_buffer_end(&mut self) {
+        self.record_jump();
+        let idx = self.text().len();
+        self.set_cursor(idx);
+    }
+
+    /// moves the cursor back into the text after it has changed underneath
+    /// it, keeping its line and offset where possible.
+    pub(crate) fn clamp_cursor(&mut self) {
+        let text = self.text();
+        let line = self.line.min(text.lenlines());
+        let start = text.line_start(line);
+        let idx = (start + self.offset).min(text.line_end(line));
+        self.set_cursor(idx);
+    }
+
+    fn move_to_line(&mut self, lnum: usize) {
+        self.break_typing();
+        let text = self.text();
+        let goal = self.goal_col.unwrap_or_else(|| self.column());
+        let lnum = lnum.min(text.lenlines());
+        let idx = text.byte_at_column(lnum, goal, self.tab_width);
+        self.place(idx);
+        self.goal_col = Some(goal);
+        if !self.extending {
+            self.anchor = self.cursor();
+        }
+    }
+
+    pub(crate) fn first_non_blank(&self, lnum: usize) -> usize {
+        let text = self.text();
+        text.chars_from(text.line_start(lnum))
+            .find(|&(_, ch)| ch == '\n' || !ch.is_whitespace())
+            .map_or(text.len(), |(i, _)| i)
+    }
+
+    /// moves the cursor to `idx` like set_cursor, but only the selection
+    /// that is being worked on. its anchor is left behind while extending.
+    pub(crate) fn move_cursor(&mut self, idx: usize) {
+        self.break_typing();
+        self.place(idx);
+        self.goal_col = None;
+        if !self.extending {
+            self.anchor = self.cursor();
+        }
+    }
+
+    /// puts the cursor at the start of the grapheme containing `idx`.
+    pub(crate) fn place(&mut self, idx: usize) {
+        let text = self.text();
+        let idx = if idx >= text.len() {
+            text.len()
+        } else {
+            text.prev_grapheme_boundary(text.next_grapheme_boundary(idx))
+        };
+        let line = text.line_at(idx);
+        self.offset = idx - text.line_start(line);
+        self.line = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::rope::Rope;
+    use crate::boundary::WordKind;
+
+    fn buffer(parts: &[&str]) -> Buffer {
+        Buffer::from(parts.iter().fold(Rope::from(""), |r, s| Rope::concat(&r, &Rope::from(*s))))
+    }
+
+    #[test]
+    fn test_set_cursor() {
+        let mut b = buffer(&["ab\nc", "e\u{301}f\n"]);
+        b.set_cursor(4);
+        assert_eq!((b.cursor(), b.line(), b.offset()), (4, 1, 1));
+        // inside of a grapheme
+        b.set_cursor(6);
+        assert_eq!(b.cursor(), 4);
+        b.set_cursor(100);
+        assert_eq!((b.cursor(), b.line(), b.offset()), (9, 2, 0));
+    }
+
+    #[test]
+    fn test_move_left_right() {
+        let mut b = buffer(&["ae\u{301}", "\nx"]);
+        let mut stops = vec![];
+        for _ in 0..5 {
+            b.move_right();
+            stops.push(b.cursor());
+        }
+        assert_eq!(stops, vec![1, 4, 5, 6, 6]);
+        stops.clear();
+        for _ in 0..5 {
+            b.move_left();
+            stops.push(b.cursor());
+        }
+        assert_eq!(stops, vec![5, 4, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_move_up_down() {
+        let mut b = buffer(&["hello world\n", "\tx\n", "日本語です\nab"]);
+        b.set_cursor(9);
+        assert_eq!(b.column(), 9);
+        b.move_down();
+        assert_eq!((b.line(), b.offset()), (1, 2));
+        b.move_down();
+        // column 9 is the second half of `で`
+        assert_eq!((b.line(), b.offset()), (2, 12));
+        b.move_down();
+        assert_eq!((b.line(), b.offset()), (3, 2));
+        b.move_down();
+        assert_eq!((b.line(), b.offset()), (3, 2));
+        b.page_up(3);
+        assert_eq!((b.line(), b.offset()), (0, 9));
+        b.move_up();
+        assert_eq!((b.line(), b.offset()), (0, 9));
+
+        b.set_tab_width(4);
+        b.set_cursor(4);
+        b.move_down();
+        assert_eq!((b.line(), b.offset()), (1, 1));
+        b.page_down(10);
+        assert_eq!((b.line(), b.offset()), (3, 2));
+    }
+
+    #[test]
+    fn test_move_line_end_sticks() {
+        let mut b = buffer(&["abc\nabcdef\nab"]);
+        b.move_line_end();
+        assert_eq!(b.cursor(), 3);
+        b.move_down();
+        assert_eq!(b.cursor(), 10);
+        b.move_down();
+        assert_eq!(b.cursor(), 13);
+        b.move_left();
+        b.move_up();
+        assert_eq!(b.cursor(), 5);
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let mut b = buffer(&["foo.bar ", " baz\nqux"]);
+        b.move_word_forward(WordKind::Word);
+        assert_eq!(b.cursor(), 3);
+        b.move_word_forward(WordKind::BigWord);
+        assert_eq!(b.cursor(), 9);
+        b.move_word_end(WordKind::Word);
+        assert_eq!(b.cursor(), 11);
+        b.move_word_end(WordKind::Word);
+        assert_eq!(b.cursor(), 15);
+        b.move_word_backward(WordKind::Word);
+        assert_eq!(b.cursor(), 13);
+        b.move_word_backward(WordKind::BigWord);
+        assert_eq!(b.cursor(), 9);
+        b.move_word_backward(WordKind::BigWord);
+        assert_eq!(b.cursor(), 0);
+    }
+
+    #[test]
+    fn test_line_motions() {
+        let mut b = buffer(&["  \tfoo bar\n", "\n  x"]);
+        b.set_cursor(6);
+        b.move_line_start();
+        assert_eq!(b.cursor(), 0);
+        b.move_first_non_blank();
+        assert_eq!(b.cursor(), 3);
+        b.move_line_end();
+        assert_eq!(b.cursor(), 10);
+        b.goto_line(1);
+        assert_eq!(b.cursor(), 11);
+        b.goto_line(2);
+        assert_eq!(b.cursor(), 14);
+        b.goto_line(50);
+        assert_eq!(b.cursor(), 14);
+        b.move_buffer_start();
+        assert_eq!(b.cursor(), 0);
+        b.move_buffer_end();
+        assert_eq!(b.cursor(), 15);
+    }
+}
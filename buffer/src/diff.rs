@@ -0,0 +1,169 @@
+//! line based diffs, and three-way merges built on them.
+
+/// the marker lines written around a conflict by merge3
+pub const CONFLICT_START: &str = "<<<<<<< local\n";
+pub const CONFLICT_MIDDLE: &str = "=======\n";
+pub const CONFLICT_END: &str = ">>>>>>> disk\n";
+
+/// splits `text` into lines, keeping their newlines.
+pub fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// finds a longest common subsequence of `a` and `b` with Myers'
+/// algorithm, as the pairs of indices of the lines that match.
+pub fn matches(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    // lines before and after the changes are matched without the
+    // algorithm, which needs memory for every step it takes
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (ma, mb) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut pairs: Vec<_> = (0..prefix).map(|i| (i, i)).collect();
+    pairs.extend(myers(ma, mb).into_iter().map(|(i, j)| (i + prefix, j + prefix)));
+    pairs.extend((0..suffix).map(|i| (a.len() - suffix + i, b.len() - suffix + i)));
+    pairs
+}
+
+/// true if the path to diagonal `k` at step `d` comes from diagonal `k + 1`,
+/// given the furthest x reached on each diagonal before the step.
+fn goes_down(v: impl Fn(isize) -> isize, k: isize, d: isize) -> bool {
+    k == -d || (k != d && v(k - 1) < v(k + 1))
+}
+
+fn myers(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let off = n + m + 1;
+    let mut v = vec![0; 2 * off as usize + 1];
+    // the furthest x reached on diagonals -d-1..=d+1, before each step d.
+    // step d only looks at those, so keeping the rest would take O((n+m)d).
+    let mut trace = vec![];
+
+    'search: for d in 0..=n + m {
+        trace.push(v[(off - d - 1) as usize..=(off + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if goes_down(|k| v[(k + off) as usize], k, d) {
+                v[(k + 1 + off) as usize]
+            } else {
+                v[(k - 1 + off) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + off) as usize] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if goes_down(at, k, d) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// merges the changes made to `base` in `local` and in `disk`. where both
+/// changed the same lines differently, both versions are kept between
+/// conflict markers. returns the merged text and the number of conflicts.
+pub fn merge3(base: &str, local: &str, disk: &str) -> (String, usize) {
+    let (base, local, disk) = (lines(base), lines(local), lines(disk));
+
+    // base lines that are unchanged in both versions split the texts into
+    // chunks, which are merged on their own
+    let mut in_local = vec![None; base.len()];
+    for (b, l) in matches(&base, &local) {
+        in_local[b] = Some(l);
+    }
+    let mut stable: Vec<_> = matches(&base, &disk).into_iter()
+        .filter_map(|(b, d)| in_local[b].map(|l| (b, l, d)))
+        .collect();
+    stable.push((base.len(), local.len(), disk.len()));
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let (mut pb, mut pl, mut pd) = (0, 0, 0);
+    for (b, l, d) in stable {
+        let (cb, cl, cd) = (&base[pb..b], &local[pl..l], &disk[pd..d]);
+        if cl == cb || cl == cd {
+            out.extend(cd.iter().copied());
+        } else if cd == cb {
+            out.extend(cl.iter().copied());
+        } else {
+            conflicts += 1;
+            for (marker, chunk) in [(CONFLICT_START, cl), (CONFLICT_MIDDLE, cd)].iter() {
+                out.push_str(marker);
+                out.extend(chunk.iter().copied());
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            out.push_str(CONFLICT_END);
+        }
+        if let Some(line) = base.get(b) {
+            out.push_str(line);
+        }
+        pb = b + 1;
+        pl = l + 1;
+        pd = d + 1;
+    }
+    (out, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{lines, matches, merge3};
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines("a\nb\n\nc"), vec!["a\n", "b\n", "\n", "c"]);
+        assert!(lines("").is_empty());
+    }
+
+    #[test]
+    fn test_matches() {
+        let a = lines("a\nb\nc\na\nb\nb\na\n");
+        let b = lines("c\nb\na\nb\na\nc\n");
+        let pairs = matches(&a, &b);
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert!(pairs.iter().all(|&(i, j)| a[i] == b[j]));
+
+        assert_eq!(matches(&lines("x\ny\n"), &[]), vec![]);
+        assert_eq!(matches(&lines("x\ny\nz\n"), &lines("x\nz\n")), vec![(0, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base = "one\ntwo\nthree\nfour\n";
+        assert_eq!(merge3(base, base, base), (base.to_string(), 0));
+        assert_eq!(merge3(base, "zero\none\ntwo\nthree\nfour\n", "one\ntwo\nthree\nfour!\n"),
+                   ("zero\none\ntwo\nthree\nfour!\n".to_string(), 0));
+        // the same change on both sides is not a conflict
+        assert_eq!(merge3(base, "one\nthree\nfour\n", "one\nthree\nfour\n"),
+                   ("one\nthree\nfour\n".to_string(), 0));
+        assert_eq!(merge3(base, "one\n2\nthree\nfour", "one\ntwo!\nthree\n"),
+                   (["one\n", "<<<<<<< local\n2\n=======\ntwo!\n>>>>>>> disk\n", "three\n",
+                     "<<<<<<< local\nfour\n=======\n>>>>>>> disk\n"].concat(), 2));
+    }
+}
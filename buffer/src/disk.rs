@@ -0,0 +1,213 @@
+//! notices when a buffer's file is changed by another program, and brings
+//! those changes into the buffer.
+//!
+//! changes are found by polling: the file's modification time and size are
+//! compared with those seen when it was last loaded or saved, and the
+//! contents are only read and hashed when those differ.
+use std::fs;
+use std::io;
+use std::iter;
+use std::time::SystemTime;
+use crate::{Buffer, no_file_error};
+use crate::diff::merge3;
+use crate::hash::hash_chunks;
+use crate::rope::Rope;
+use crate::undofile::Delta;
+
+/// what the buffer knows about its file, from when it was last loaded or
+/// saved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DiskInfo {
+    modified: Option<SystemTime>,
+    len: u64,
+    pub(crate) hash: u64,
+}
+
+/// how a buffer's file has changed since it was last loaded or saved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskChange {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+/// what to do with the edits in a buffer when its file has changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reload {
+    /// replace the text with the file's, as one edit that can be undone
+    Discard,
+    /// keep the text as it is. it will count as modified from now on
+    KeepLocal,
+    /// merge the changes to the file with the edits made since it was last
+    /// loaded or saved, as one edit that can be undone
+    Merge,
+}
+
+impl Buffer {
+    /// remembers the state of the buffer's file, which has just been loaded
+    /// or saved with contents that hash to `hash`.
+    pub(crate) fn record_disk(&mut self, hash: u64) {
+        let meta = self.filename.as_ref().and_then(|name| fs::metadata(name).ok());
+        self.disk = Some(DiskInfo {
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            len: meta.map_or(0, |m| m.len()),
+            hash,
+        });
+    }
+
+    /// checks whether the buffer's file has been changed by something else
+    /// since it was last loaded or saved. meant to be polled.
+    pub fn check_disk(&mut self) -> io::Result<DiskChange> {
+        let name = match &self.filename {
+            Some(name) => name.clone(),
+            None => return Ok(DiskChange::Unchanged),
+        };
+        let meta = match fs::metadata(&name) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DiskChange::Deleted),
+            meta => meta?,
+        };
+        let modified = meta.modified().ok();
+        if let Some(disk) = self.disk {
+            if modified.is_some() && disk.modified == modified && disk.len == meta.len() {
+                return Ok(DiskChange::Unchanged);
+            }
+        }
+
+        // the file was touched, but its contents may be the same
+        let hash = hash_chunks(iter::once(&fs::read(&name)?[..]));
+        match &mut self.disk {
+            Some(disk) if disk.hash == hash => {
+                disk.modified = modified;
+                disk.len = meta.len();
+                Ok(DiskChange::Unchanged)
+            },
+            _ => Ok(DiskChange::Modified),
+        }
+    }
+
+    /// brings the contents of the buffer's file into the buffer as `how`
+    /// says, keeping the undo history. returns the number of conflicts the
+    /// merge marked in the text, which is 0 for the other strategies.
+    ///
+    /// merging needs the text that was last loaded or saved, so it fails if
+    /// that is not in the history anymore, as after keeping local edits.
+    pub fn reload_with(&mut self, how: Reload) -> io::Result<usize> {
+        let name = self.filename.clone().ok_or_else(no_file_error)?;
+        let disk = fs::read_to_string(&name)?;
+
+        let conflicts = match how {
+            Reload::Discard => {
+                self.replace_all(&disk);
+                0
+            },
+            Reload::KeepLocal => 0,
+            Reload::Merge => {
+                let base = self.saved.ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the text last loaded or saved is not known"))?;
+                let base: String = self.history[base].text.str_iter().collect();
+                let local: String = self.text().str_iter().collect();
+                let (merged, conflicts) = merge3(&base, &local, &disk);
+                self.replace_all(&merged);
+                conflicts
+            },
+        };
+
+        self.saved = if *self.text() == *disk {
+            Some(self.current)
+        } else {
+            None
+        };
+        self.record_disk(hash_chunks(iter::once(disk.as_bytes())));
+        self.start_swap(&Rope::from(&*disk));
+        Ok(conflicts)
+    }
+
+    /// replaces the whole text with `text` as one edit, keeping the cursor
+    /// on the same line and offset where it can. only the part that differs
+    /// is replaced, so marks outside of it stay put.
+    fn replace_all(&mut self, text: &str) {
+        if *self.text() == *text {
+            return;
+        }
+        let (line, offset) = (self.line, self.offset);
+        let delta = Delta::between(self.text(), &Rope::from(text));
+        let end = delta.start + delta.removed.len();
+        self.edit(delta.start, end, &delta.inserted, delta.start);
+        self.line = line;
+        self.offset = offset;
+        self.clamp_cursor();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::disk::{DiskChange, Reload};
+    use crate::testutil::{load, temp_file};
+
+    #[test]
+    fn test_check_disk() {
+        let (_dir, path) = temp_file("disk-check", "abc\n");
+        let mut b = load(&path);
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Unchanged);
+
+        // rewriting the same contents is not a change
+        fs::write(&path, "abc\n").unwrap();
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Unchanged);
+        fs::write(&path, "abd\n").unwrap();
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Modified);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Deleted);
+
+        b.save().unwrap();
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Unchanged);
+    }
+
+    #[test]
+    fn test_reload_discard_keep() {
+        let (_dir, path) = temp_file("disk-discard", "one\ntwo\n");
+        let mut b = load(&path);
+        b.goto_line(1);
+        b.insert_at_cursor("2 ");
+        fs::write(&path, "one\nthree\n").unwrap();
+
+        assert_eq!(b.reload_with(Reload::KeepLocal).unwrap(), 0);
+        assert_eq!(*b.text(), *"one\n2 two\n");
+        assert!(b.is_modified());
+        assert_eq!(b.check_disk().unwrap(), DiskChange::Unchanged);
+        assert!(b.reload_with(Reload::Merge).is_err());
+
+        assert_eq!(b.reload_with(Reload::Discard).unwrap(), 0);
+        assert_eq!(*b.text(), *"one\nthree\n");
+        assert_eq!((b.line(), b.offset()), (1, 2));
+        assert!(!b.is_modified());
+        b.undo();
+        assert_eq!(*b.text(), *"one\n2 two\n");
+    }
+
+    #[test]
+    fn test_reload_merge() {
+        let (_dir, path) = temp_file("disk-merge", "a\nb\nc\nd\ne\n");
+        let mut b = load(&path);
+        b.insert_at_cursor("A");
+        b.goto_line(3);
+        b.insert_at_cursor("D");
+        fs::write(&path, "a\nb\nc!\nd!\ne\n").unwrap();
+
+        assert_eq!(b.reload_with(Reload::Merge).unwrap(), 1);
+        assert_eq!(*b.text(), *"Aa\nb\n<<<<<<< local\nc\nDd\n=======\nc!\nd!\n>>>>>>> disk\ne\n");
+        assert!(b.is_modified());
+        b.undo();
+        assert_eq!(*b.text(), *"Aa\nb\nc\nDd\ne\n");
+
+        // the same change on both sides leaves nothing to save
+        let (_dir, path) = temp_file("disk-merge-same", "x\ny\n");
+        let mut b = load(&path);
+        b.insert_at_cursor("X");
+        fs::write(&path, "Xx\ny\n").unwrap();
+        assert_eq!(b.reload_with(Reload::Merge).unwrap(), 0);
+        assert_eq!(*b.text(), *"Xx\ny\n");
+        assert!(!b.is_modified());
+    }
+}
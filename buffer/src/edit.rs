@@ -0,0 +1,283 @@
+//! editing operations on a buffer. every edit goes through `Buffer::edit`,
+//! or `edit_ranges` for many at once, which record a new revision of the
+//! text for undo.
+use std::ops::{Range, RangeBounds};
+use crate::{Buffer, Result};
+use crate::rope::Rope;
+use crate::undo::Edit;
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t'
+}
+
+impl Buffer {
+    /// inserts `text` at the cursor, leaving the cursor after it. a
+    /// selection that is not empty is replaced by `text`.
+    pub fn insert_at_cursor(&mut self, text: &str) {
+        self.each_selection_typing(text, |b| b.insert_text(text));
+    }
+
+    /// splits the line at the cursor. the new line is indented with the
+    /// spaces and tabs that start the current one, and the cursor is left
+    /// after them.
+    pub fn insert_newline(&mut self) {
+        self.each_selection(|b| {
+            let idx = b.selection().start();
+            let start = b.text().line_start(b.text().line_at(idx));
+            let indent: String = b.text().chars_from(start)
+                .take_while(|&(i, ch)| i < idx && is_blank(ch))
+                .map(|(_, ch)| ch)
+                .collect();
+            b.insert_text(&format!("\n{}", indent));
+        });
+    }
+
+    /// deletes the grapheme before the cursor, joining the line onto the
+    /// previous one if the cursor is at its start. a selection that is not
+    /// empty is deleted instead.
+    pub fn backspace(&mut self) {
+        self.each_selection(|b| {
+            if !b.selection().is_empty() {
+                return b.insert_text("");
+            }
+            let end = b.cursor();
+            let start = b.text().prev_grapheme_boundary(end);
+            b.edit(start, end, "", start);
+        });
+    }
+
+    /// deletes the grapheme after the cursor, joining the next line onto
+    /// this one if the cursor is at its end. a selection that is not empty
+    /// is deleted instead.
+    pub fn delete_forward(&mut self) {
+        self.each_selection(|b| {
+            if !b.selection().is_empty() {
+                return b.insert_text("");
+            }
+            let start = b.cursor();
+            let end = b.text().next_grapheme_boundary(start);
+            b.edit(start, end, "", start);
+        });
+    }
+
+    /// deletes the bytes in `r`, leaving the cursor where they were. fails
+    /// if `r` is out of bounds, reversed, or does not fall on char
+    /// boundaries.
+    pub fn delete_range(&mut self, r: impl RangeBounds<usize>) -> Result<()> {
+        self.replace_range(r, "")
+    }
+
+    /// replaces the bytes in `r` with `text`, leaving the cursor at the
+    /// start of `text`. fails like delete_range.
+    pub fn replace_range(&mut self, r: impl RangeBounds<usize>, text: &str) -> Result<()> {
+        let (start, end) = self.text().check_range(&r)?;
+        self.edit(start, end, text, start);
+        Ok(())
+    }
+
+    /// joins the next line onto the cursor's line like vim's `J`. the
+    /// indentation of the next line is replaced with one space, unless the
+    /// cursor's line ends in a space, or the next line is blank or starts
+    /// with a `)`. the cursor is left where the lines were joined.
+    pub fn join_lines(&mut self) {
+        self.each_selection(Buffer::join_line);
+    }
+
+    fn join_line(&mut self) {
+        let text = self.text();
+        if self.line >= text.lenlines() {
+            return;
+        }
+        let nl = text.line_end(self.line);
+        let (end, next) = text.chars_from(nl + 1)
+            .find(|&(_, ch)| !is_blank(ch))
+            .map_or((text.len(), None), |(i, ch)| (i, Some(ch)));
+        let trailing = text.chars_before(nl).next().map(|(_, ch)| ch);
+
+        let sep = match (trailing, next) {
+            (None, _) | (_, None) | (_, Some('\n')) | (_, Some(')')) => "",
+            (Some(ch), _) if is_blank(ch) => "",
+            _ => " ",
+        };
+        self.edit(nl, end, sep, nl);
+    }
+
+    /// replaces bytes `start..end` of the text with `text` as a new version
+    /// on the undo stack, and moves the cursor to `cursor` in the new text.
+    /// edits that would not change anything are skipped.
+    pub(crate) fn edit(&mut self, start: usize, end: usize, text: &str, cursor: usize) {
+        self.interrupt_substitute();
+        if start == end && text.is_empty() {
+            self.move_cursor(cursor);
+            return;
+        }
+        let mut new = self.text().delete(start..end);
+        if !text.is_empty() {
+            new = new.insert(start, Rope::from(text));
+        }
+        let before = self.cursor();
+        self.record(new, &[Edit { start, end, len: text.len() }], text, before, cursor);
+        self.journal(start, end - start, text);
+        self.note_change();
+        self.shift_selections(start, end, text.len());
+        self.shift_marks(start, end, text.len());
+        self.shift_search(start, end, text.len());
+        self.place(cursor);
+        self.goal_col = None;
+        self.anchor = self.cursor();
+    }
+
+    /// replaces each of `replacements`, which are ranges of the text in
+    /// order that don't overlap, as one edit, and moves the cursor to
+    /// `cursor` in the new text. the new text is built once, rather than
+    /// once for every replacement.
+    pub(crate) fn edit_ranges(&mut self, replacements: &[(Range<usize>, String)], cursor: usize) {
+        let text = self.text().clone();
+        let (mut pieces, mut edits) = (vec![], vec![]);
+        let (mut at, mut grown) = (0, 0isize);
+        for (r, with) in replacements.iter().filter(|(r, with)| !r.is_empty() || !with.is_empty()) {
+            pieces.push(text.char_slice(at..r.start));
+            pieces.push(Rope::from(with.as_str()));
+            // each edit is made to the text the ones before it left
+            let start = (r.start as isize + grown) as usize;
+            edits.push(Edit { start, end: start + r.len(), len: with.len() });
+            grown += with.len() as isize - r.len() as isize;
+            at = r.end;
+        }
+        if edits.is_empty() {
+            self.move_cursor(cursor);
+            return;
+        }
+        pieces.push(text.char_slice(at..));
+
+        // joined in pairs, so the rope is no deeper than it has to be
+        while pieces.len() > 1 {
+            pieces = pieces.chunks(2).map(|pair| match pair {
+                [a, b] => Rope::concat(a, b),
+                _ => pair[0].clone(),
+            }).collect();
+        }
+        let before = self.cursor();
+        self.record(pieces.remove(0), &edits, "", before, cursor);
+        self.note_change();
+        for e in &edits {
+            self.shift_selections(e.start, e.end, e.len);
+        }
+        self.edits_made(edits);
+        self.place(cursor);
+        self.goal_col = None;
+        self.anchor = self.cursor();
+    }
+
+    /// replaces the selection with `text`, which is just an insert at the
+    /// cursor if the selection is empty.
+    fn insert_text(&mut self, text: &str) {
+        let sel = self.selection();
+        if !sel.is_empty() {
+            // replacing a selection is not part of the typing before it
+            self.break_typing();
+        }
+        self.edit(sel.start(), sel.end(), text, sel.start() + text.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::rope::Rope;
+    use crate::error::Error;
+
+    fn buffer(text: &str, cursor: usize) -> Buffer {
+        let mut b = Buffer::from(Rope::from(text));
+        b.set_cursor(cursor);
+        b
+    }
+
+    #[test]
+    fn test_insert_at_cursor() {
+        let mut b = buffer("ac\n", 1);
+        b.insert_at_cursor("b");
+        assert_eq!(*b.text(), *"abc\n");
+        assert_eq!(b.cursor(), 2);
+        b.insert_at_cursor("\nx");
+        assert_eq!(*b.text(), *"ab\nxc\n");
+        assert_eq!((b.line(), b.offset()), (1, 1));
+        assert_eq!(b.history.len(), 3);
+        b.insert_at_cursor("");
+        assert_eq!(b.history.len(), 3);
+    }
+
+    #[test]
+    fn test_insert_newline() {
+        let mut b = buffer("  \tfoo bar", 7);
+        b.insert_newline();
+        assert_eq!(*b.text(), *"  \tfoo \n  \tbar");
+        assert_eq!(b.cursor(), 11);
+        // only the indentation before the cursor is copied
+        b.set_cursor(9);
+        b.insert_newline();
+        assert_eq!(*b.text(), *"  \tfoo \n \n  \tbar");
+        assert_eq!(b.cursor(), 11);
+    }
+
+    #[test]
+    fn test_backspace_delete_forward() {
+        let mut b = buffer("ae\u{301}\nb", 4);
+        b.backspace();
+        assert_eq!(*b.text(), *"a\nb");
+        assert_eq!(b.cursor(), 1);
+        b.delete_forward();
+        assert_eq!(*b.text(), *"ab");
+        assert_eq!(b.cursor(), 1);
+        b.backspace();
+        b.backspace();
+        assert_eq!(*b.text(), *"b");
+        assert_eq!(b.cursor(), 0);
+        b.move_buffer_end();
+        b.delete_forward();
+        assert_eq!(*b.text(), *"b");
+        assert_eq!(b.history.len(), 4);
+
+        // a selection is deleted rather than the grapheme at the cursor
+        let mut b = buffer("abcd", 0);
+        b.select(1, 3);
+        b.backspace();
+        assert_eq!(*b.text(), *"ad");
+        assert_eq!(b.cursor(), 1);
+        b.select(0, 1);
+        b.delete_forward();
+        assert_eq!(*b.text(), *"d");
+        assert_eq!(b.cursor(), 0);
+    }
+
+    #[test]
+    fn test_delete_replace_range() {
+        let mut b = buffer("hello world", 10);
+        b.delete_range(5..).unwrap();
+        assert_eq!(*b.text(), *"hello");
+        assert_eq!(b.cursor(), 5);
+        b.replace_range(1..=3, "ipp").unwrap();
+        assert_eq!(*b.text(), *"hippo");
+        assert_eq!(b.cursor(), 1);
+        assert_eq!(b.replace_range(2..9, "x"), Err(Error::OutOfBounds(9)));
+        assert_eq!(*b.text(), *"hippo");
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut b = buffer("foo\n   bar\nbaz \n\tqux\n\n)", 1);
+        b.join_lines();
+        assert_eq!(*b.text(), *"foo bar\nbaz \n\tqux\n\n)");
+        assert_eq!(b.cursor(), 3);
+        b.move_down();
+        b.join_lines();
+        assert_eq!(*b.text(), *"foo bar\nbaz qux\n\n)");
+        b.join_lines();
+        assert_eq!(*b.text(), *"foo bar\nbaz qux\n)");
+        b.join_lines();
+        assert_eq!(*b.text(), *"foo bar\nbaz qux)");
+        b.join_lines();
+        assert_eq!(*b.text(), *"foo bar\nbaz qux)");
+        assert_eq!(b.history.len(), 5);
+    }
+}
@@ -0,0 +1,30 @@
+//! the error type for operations on the text of a buffer.
+use std::fmt;
+use std::result;
+
+/// an error from a checked operation on text, such as `Rope::try_insert`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// the byte offset is past the end of the text
+    OutOfBounds(usize),
+    /// the start of a range (first) is after its end (second)
+    Reversed(usize, usize),
+    /// the byte offset is inside a multi-byte character
+    NotCharBoundary(usize),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OutOfBounds(i) => write!(f, "byte {} is out of bounds", i),
+            Error::Reversed(start, end) =>
+                write!(f, "range start {} is after its end {}", start, end),
+            Error::NotCharBoundary(i) =>
+                write!(f, "byte {} is not a char boundary", i),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,34 @@
+//! a hash of file contents that is stable between runs and releases, so it
+//! can be stored on disk. std's hashers make no such promise.
+use crate::rope::Rope;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// the 64 bit FNV-1a hash of the concatenation of `chunks`
+pub fn hash_chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    chunks.into_iter()
+        .flatten()
+        .fold(FNV_OFFSET, |h, &b| (h ^ u64::from(b)).wrapping_mul(FNV_PRIME))
+}
+
+/// the hash of the text in `rope`, the same as hashing it as one string.
+pub fn hash_rope(rope: &Rope) -> u64 {
+    hash_chunks(rope.str_iter().map(str::as_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rope::Rope;
+    use crate::hash::{hash_chunks, hash_rope};
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash_chunks(vec![&b""[..]]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_chunks(vec![&b"a"[..]]), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash_chunks(vec![&b"foobar"[..]]), 0x8594_4171_f739_67e8);
+        let r = Rope::concat(&Rope::from("foo"), &Rope::from("bar"));
+        assert_eq!(hash_rope(&r), hash_chunks(vec![&b"foobar"[..]]));
+        assert_ne!(hash_rope(&r), hash_rope(&Rope::from("foobaz")));
+    }
+}
@@ -1,34 +1,104 @@
 //! # the pi buffer
 //! this crate has a the buffer of the pi text editor and associated types
 mod newlines;
+mod range;
+pub mod error;
 pub mod rcstring;
 pub mod rope;
+pub mod boundary;
+pub mod brackets;
+pub mod column;
+pub mod wrap;
+mod cursor;
+mod edit;
+pub mod undo;
+pub mod undofile;
+mod hash;
+pub mod save;
+pub mod diff;
+pub mod disk;
+pub mod swap;
+pub mod autosave;
+pub mod selection;
+pub mod visual;
+pub mod registers;
+pub mod marks;
+pub mod search;
+pub mod substitute;
+#[cfg(test)]
+mod testutil;
 
 use std::vec::Vec;
 use std::option::Option;
 use std::fs;
 use std::io;
+use std::ops::Range;
+use std::time::Instant;
 
 use crate::rope::Rope;
+use crate::column::DEFAULT_TAB_WIDTH;
+use crate::undo::{Merge, Revision};
+use crate::save::Backup;
+use crate::hash::hash_rope;
+use crate::disk::DiskInfo;
+use crate::swap::{Recovery, Swap};
+use crate::autosave::Autosave;
+use crate::selection::Selection;
+use crate::visual::VisualMode;
+use crate::search::Search;
+use crate::substitute::Substitution;
+pub use crate::error::{Error, Result};
 
 /// an editor buffer with a cursor and undo/redo functionality
 pub struct Buffer {
     filename: Option<String>,
-    undo_stack: Vec<Rope>,
+    /// every revision of the text, see the undo module
+    history: Vec<Revision>,
     line: usize,
     offset: usize,
-    undooff: usize,
+    current: usize,
+    /// the display column vertical motions try to keep the cursor in
+    goal_col: Option<usize>,
+    /// where the primary selection starts, see the selection module
+    anchor: usize,
+    /// the selections other than the primary one, in order
+    others: Vec<Selection>,
+    extending: bool,
+    visual: Option<VisualMode>,
+    /// the text put in by the last kill ring yank, and the revision it made
+    last_yank: Option<(Range<usize>, usize)>,
+    /// marks `a` to `z` and then `A` to `Z`, see the marks module
+    marks: [Option<usize>; 52],
+    jumps: Vec<usize>,
+    /// where in the jump list jump_back and jump_forward are
+    jump_pos: usize,
+    search: Search,
+    /// the `:s` waiting for answers, see the substitute module
+    substitution: Option<Substitution>,
+    tab_width: usize,
+    merge: Merge,
+    group_depth: usize,
+    backup: Backup,
+    /// the revision that was last loaded or saved
+    saved: Option<usize>,
+    /// the state of the file when it was last loaded or saved
+    disk: Option<DiskInfo>,
+    /// the journal of changes since then, see the swap module
+    swap: Option<Swap>,
+    /// changes left in the swap file by an editor that did not close it
+    recovery: Option<Recovery>,
+    autosave: Autosave,
+    read_only: bool,
+    /// the number of changes to the text since it was last saved
+    edits: usize,
+    /// when the text last changed, if it has since it was last saved
+    changed: Option<Instant>,
 }
 
 impl Buffer {
     pub fn new(filename: Option<String>) -> io::Result<Self> {
-        let mut buff = Buffer {
-            filename: filename,
-            undo_stack: Vec::new(),
-            line: 0,
-            offset: 0,
-            undooff: 0,
-        };
+        let mut buff = Buffer::from(Rope::from(""));
+        buff.filename = filename;
 
         if let Some(name) = buff.filename.clone() {
             buff.load_file(name)?
@@ -38,21 +108,88 @@ impl Buffer {
     }
 
     pub fn load_file(& mut self, name: String) -> io::Result<()> {
-        self.undo_stack = vec![Rope::from(fs::read_to_string(&name)?)];
-        self.undooff = 0;
+        self.history = vec![Revision::new(Rope::from(fs::read_to_string(&name)?))];
+        self.current = 0;
+        self.merge = Merge::Off;
+        self.read_only = fs::metadata(&name)?.permissions().readonly();
         self.filename = Some(name);
+        // an undo file that is missing or out of date is not an error
+        let _ = self.read_undo_file();
+        self.saved = Some(self.current);
+        self.record_disk(hash_rope(self.text()));
+        self.clamp_cursor();
+        self.recovery = None;
+        self.edits = 0;
+        self.changed = None;
+        self.marks = [None; 52];
+        self.jumps.clear();
+        self.jump_pos = 0;
+        self.start_swap(&self.text().clone());
         Ok(())
     }
 
+    /// the current text of the buffer
+    pub fn text(&self) -> &Rope {
+        &self.history[self.current].text
+    }
+
+    /// the width of a tab, for working out display columns
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+        self.goal_col = None;
+    }
+
     pub fn reload(& mut self) -> io::Result<()> {
         match self.filename.clone() {
             Some(name) => {
                 self.load_file(name)?;
                 Ok(())
             },
-            None => Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "buffer has no associated file")),
+            None => Err(no_file_error()),
+        }
+    }
+}
+
+pub(crate) fn no_file_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "buffer has no associated file")
+}
+
+impl From<Rope> for Buffer {
+    /// creates a buffer with no associated file, containing `text`.
+    fn from(text: Rope) -> Self {
+        Buffer {
+            filename: None,
+            history: vec![Revision::new(text)],
+            line: 0,
+            offset: 0,
+            current: 0,
+            goal_col: None,
+            anchor: 0,
+            others: vec![],
+            extending: false,
+            visual: None,
+            last_yank: None,
+            marks: [None; 52],
+            jumps: vec![],
+            jump_pos: 0,
+            search: Search::default(),
+            substitution: None,
+            tab_width: DEFAULT_TAB_WIDTH,
+            merge: Merge::Off,
+            group_depth: 0,
+            backup: Backup::None,
+            saved: Some(0),
+            disk: None,
+            swap: None,
+            recovery: None,
+            autosave: Autosave::Off,
+            read_only: false,
+            edits: 0,
+            changed: None,
         }
     }
 }
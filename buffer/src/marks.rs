@@ -0,0 +1,305 @@
+//! marks, and the jump list, as in vim.
+//!
+//! marks `a` to `z` belong to a buffer. marks `A` to `Z` are global: each
+//! is in one file, which is kept track of by `GlobalMarks`, but its
+//! position lives in the buffer editing that file while there is one.
+//!
+//! the jump list holds where the cursor was before each large motion, like
+//! going to a line or a mark, or a search. marks and jumps are byte offsets
+//! that move with the text as it is edited, like selections.
+use crate::Buffer;
+use crate::selection::shift;
+
+/// the most jumps a buffer keeps, which is vim's limit
+pub const JUMP_LIST_MAX: usize = 100;
+
+/// where `name` is kept in a buffer's marks, lowercase ones first
+fn mark_index(name: char) -> Option<usize> {
+    match name {
+        'a'..='z' => Some(name as usize - 'a' as usize),
+        'A'..='Z' => Some(26 + name as usize - 'A' as usize),
+        _ => None,
+    }
+}
+
+/// which file each global mark is in, and where, as of the last time the
+/// buffer editing it stored its marks here
+#[derive(Clone, Debug, Default)]
+pub struct GlobalMarks {
+    marks: [Option<(String, usize)>; 26],
+}
+
+impl GlobalMarks {
+    pub fn new() -> Self {
+        GlobalMarks::default()
+    }
+
+    /// the file global mark `name` is in, and its byte offset there
+    pub fn get(&self, name: char) -> Option<(&str, usize)> {
+        match name {
+            'A'..='Z' => {
+                let (file, idx) = self.marks[name as usize - 'A' as usize].as_ref()?;
+                Some((file, *idx))
+            },
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, name: char, file: &str, idx: usize) {
+        self.marks[name as usize - 'A' as usize] = Some((file.to_string(), idx));
+    }
+}
+
+impl Buffer {
+    /// puts mark `name` at the cursor. an uppercase mark is global, and can
+    /// only be set in a buffer with a file. returns false if `name` is not a
+    /// mark that can be set here.
+    pub fn set_mark(&mut self, name: char, globals: &mut GlobalMarks) -> bool {
+        let i = match mark_index(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        if name.is_ascii_uppercase() {
+            match &self.filename {
+                Some(file) => globals.set(name, file, self.cursor()),
+                None => return false,
+            }
+        }
+        self.marks[i] = Some(self.cursor());
+        true
+    }
+
+    /// where mark `name` is in this buffer. a global mark only counts if it
+    /// has not been set in another file since. one stored in `globals` is
+    /// taken into the buffer the first time it is looked up, so it moves
+    /// with the text from then on.
+    pub fn mark(&mut self, name: char, globals: &GlobalMarks) -> Option<usize> {
+        let i = mark_index(name)?;
+        if name.is_ascii_uppercase() {
+            let (file, idx) = globals.get(name)?;
+            if Some(file) != self.filename.as_deref() {
+                return None;
+            }
+            let len = self.text().len();
+            return Some(*self.marks[i].get_or_insert(idx.min(len)));
+        }
+        self.marks[i]
+    }
+
+    /// moves the cursor to mark `name`, as a jump. returns false if the mark
+    /// is not set in this buffer. the editor can look a global mark up in
+    /// `globals` to find the file to go to first.
+    pub fn goto_mark(&mut self, name: char, globals: &GlobalMarks) -> bool {
+        match self.mark(name, globals) {
+            Some(idx) => {
+                self.record_jump();
+                self.set_cursor(idx);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// stores where this buffer's global marks are now in `globals`, so
+    /// they can be found once the buffer is gone. the editor should do this
+    /// before closing or leaving the buffer.
+    pub fn store_global_marks(&self, globals: &mut GlobalMarks) {
+        let file = match &self.filename {
+            Some(file) => file,
+            None => return,
+        };
+        for name in 'A'..='Z' {
+            let idx = self.marks[mark_index(name).unwrap()];
+            if let (Some(idx), Some((owner, _))) = (idx, globals.get(name)) {
+                if owner == file {
+                    globals.set(name, file, idx);
+                }
+            }
+        }
+    }
+
+    /// adds the cursor to the jump list, before a large motion away from
+    /// it. jumps made by the editor, like going to a definition, call this
+    /// themselves. an older jump on the same line is dropped.
+    pub fn record_jump(&mut self) {
+        let text = self.history[self.current].text.clone();
+        let line = self.line;
+        self.jumps.retain(|&idx| text.line_at(idx.min(text.len())) != line);
+        self.jumps.push(self.cursor());
+        if self.jumps.len() > JUMP_LIST_MAX {
+            self.jumps.remove(0);
+        }
+        self.jump_pos = self.jumps.len();
+    }
+
+    /// goes back to the last jump, like vim's `ctrl+o`. returns false if
+    /// there is none.
+    pub fn jump_back(&mut self) -> bool {
+        if self.jump_pos == 0 {
+            return false;
+        }
+        // coming back from the end of the list should find the cursor again
+        if self.jump_pos == self.jumps.len() {
+            self.record_jump();
+            if self.jump_pos == 1 {
+                return false;
+            }
+            self.jump_pos -= 1;
+        }
+        self.jump_pos -= 1;
+        self.set_cursor(self.jumps[self.jump_pos]);
+        true
+    }
+
+    /// goes forward through the jump list again, like vim's `ctrl+i`.
+    /// returns false if at the newest jump already.
+    pub fn jump_forward(&mut self) -> bool {
+        if self.jump_pos + 1 >= self.jumps.len() {
+            return false;
+        }
+        self.jump_pos += 1;
+        self.set_cursor(self.jumps[self.jump_pos]);
+        true
+    }
+
+    /// the jump list, oldest first, and where in it the cursor is. that is
+    /// the length of the list if it hasn't jumped back.
+    pub fn jump_list(&self) -> (&[usize], usize) {
+        (&self.jumps, self.jump_pos)
+    }
+
+    /// moves the marks and jumps through the replacement of bytes
+    /// `start..end` with `len` new ones.
+    pub(crate) fn shift_marks(&mut self, start: usize, end: usize, len: usize) {
+        let marks = self.marks.iter_mut().flatten();
+        for idx in marks.chain(self.jumps.iter_mut()) {
+            *idx = shift(*idx, start, end, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::marks::GlobalMarks;
+    use crate::rope::Rope;
+    use crate::testutil::{TempDir, load, temp_file};
+
+    #[test]
+    fn test_local_marks() {
+        let mut globals = GlobalMarks::new();
+        let mut b = Buffer::from(Rope::from("one\ntwo\nthree"));
+        b.set_cursor(8);
+        assert!(b.set_mark('a', &mut globals));
+        assert!(!b.set_mark('A', &mut globals));
+        assert!(!b.set_mark('1', &mut globals));
+
+        b.set_cursor(0);
+        b.insert_at_cursor("zero\n");
+        assert_eq!(b.mark('a', &globals), Some(13));
+        b.set_cursor(15);
+        b.insert_at_cursor("!");
+        assert_eq!(b.mark('a', &globals), Some(13));
+        assert!(b.goto_mark('a', &globals));
+        assert_eq!(b.cursor(), 13);
+        assert!(!b.goto_mark('b', &globals));
+
+        // undo moves marks back
+        b.undo();
+        b.undo();
+        assert_eq!(*b.text(), *"one\ntwo\nthree");
+        assert_eq!(b.mark('a', &globals), Some(8));
+
+        // and follow every edit of a group, and jumps between branches
+        b.begin_group();
+        b.replace_range(12..12, "X").unwrap();
+        b.replace_range(1..1, "Y").unwrap();
+        b.end_group();
+        assert_eq!(b.mark('a', &globals), Some(9));
+        b.undo();
+        assert_eq!(b.mark('a', &globals), Some(8));
+        b.set_cursor(9);
+        b.insert_at_cursor("ZZ");
+        assert_eq!(b.mark('a', &globals), Some(8));
+        assert!(b.goto_revision(3));
+        assert_eq!(*b.text(), *"oYne\ntwo\nthreXe");
+        assert_eq!(b.mark('a', &globals), Some(9));
+    }
+
+    #[test]
+    fn test_global_marks() {
+        let dir = TempDir::new("marks-global");
+        let one = dir.file("one.txt", "abc\ndef\n");
+        let two = dir.file("two.txt", "xyz\n");
+        let mut globals = GlobalMarks::new();
+
+        let mut b1 = load(&one);
+        let mut b2 = load(&two);
+        b1.set_cursor(4);
+        assert!(b1.set_mark('A', &mut globals));
+        assert_eq!(globals.get('A'), Some((one.to_str().unwrap(), 4)));
+        assert_eq!(b2.mark('A', &globals), None);
+
+        b1.set_cursor(0);
+        b1.insert_at_cursor("!!");
+        assert_eq!(b1.mark('A', &globals), Some(6));
+        b1.store_global_marks(&mut globals);
+        assert_eq!(globals.get('A'), Some((one.to_str().unwrap(), 6)));
+        b1.save().unwrap();
+        drop(b1);
+
+        // the mark is found again when the file is opened again, and moves
+        // with its text from then on
+        let mut b1 = load(&one);
+        assert!(b1.goto_mark('A', &globals));
+        assert_eq!(b1.cursor(), 6);
+        b1.set_cursor(0);
+        b1.insert_at_cursor("?");
+        assert_eq!(b1.mark('A', &globals), Some(7));
+
+        assert!(b2.set_mark('A', &mut globals));
+        assert_eq!(b1.mark('A', &globals), None);
+        b1.store_global_marks(&mut globals);
+        assert_eq!(globals.get('A'), Some((two.to_str().unwrap(), 0)));
+    }
+
+    #[test]
+    fn test_jump_list() {
+        let mut b = Buffer::from(Rope::from("l0\nl1\nl2\nl3\n"));
+        assert!(!b.jump_back());
+        b.goto_line(2);
+        b.goto_line(3);
+        assert!(b.jump_back());
+        assert_eq!(b.cursor(), 6);
+        assert!(b.jump_back());
+        assert_eq!(b.cursor(), 0);
+        assert!(!b.jump_back());
+        assert!(b.jump_forward());
+        assert!(b.jump_forward());
+        assert_eq!(b.cursor(), 9);
+        assert!(!b.jump_forward());
+
+        // jumps move with the text
+        b.set_cursor(0);
+        b.insert_at_cursor("zz\n");
+        assert_eq!(b.jump_list(), (&[0, 9, 12][..], 2));
+
+        // jumping from the same line again replaces the old jump
+        b.set_cursor(10);
+        b.record_jump();
+        assert_eq!(b.jump_list(), (&[0, 12, 10][..], 3));
+    }
+
+    #[test]
+    fn test_load_resets() {
+        let (_dir, path) = temp_file("marks-load", "abc\ndef\n");
+        let mut globals = GlobalMarks::new();
+        let mut b = load(&path);
+        b.set_cursor(5);
+        b.set_mark('a', &mut globals);
+        b.goto_line(1);
+        b.load_file(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(b.mark('a', &globals), None);
+        assert_eq!(b.jump_list(), (&[][..], 0));
+    }
+}
@@ -25,7 +25,7 @@ SOFTWARE.
 #[cfg(target_pointer_width = "16")] const USIZE_BYTES: usize = 2;
 #[cfg(target_pointer_width = "32")] const USIZE_BYTES: usize = 4;
 #[cfg(target_pointer_width = "64")] const USIZE_BYTES: usize = 8;
-const LO : usize = ::std::usize::MAX / 255;
+const LO : usize = usize::MAX / 255;
 const HI : usize = LO * 128;
 const REP_NEWLINE : usize = b'\n' as usize * LO;
 
@@ -34,11 +34,10 @@ const EVERY_OTHER_BYTE : usize = EVERY_OTHER_BYTE_LO * 0xFF;
 
 /// counts newlines and only newlines very fast
 /// see https://github.com/llogiq/newlinebench
-pub fn count_newlines(s: &str) -> usize {
+pub fn count_newlines(text: &[u8]) -> usize {
     unsafe {
-        let text = s.as_bytes();
         let mut ptr = text.as_ptr();
-        let mut end = ptr.offset(text.len() as isize);
+        let mut end = ptr.add(text.len());
 
         let mut count = 0;
 
@@ -77,7 +76,7 @@ pub fn count_newlines(s: &str) -> usize {
         unsafe fn next_4(ptr: &mut *const usize) -> [usize; 4] {
             let x = [next(ptr), next(ptr), next(ptr), next(ptr)];
             [mask_zero(x[0]), mask_zero(x[1]), mask_zero(x[2]), mask_zero(x[3])]
-        };
+        }
 
         fn reduce_counts(counts: usize) -> usize {
             let pair_sum = (counts & EVERY_OTHER_BYTE) + ((counts >> 8) & EVERY_OTHER_BYTE);
@@ -0,0 +1,73 @@
+//! helpers for turning `RangeBounds` into concrete byte ranges.
+use std::ops::{RangeBounds, Bound};
+use crate::error::{Error, Result};
+
+/// resolves `r` into a `(start, end)` pair within `0..=len`.
+/// out of bounds ends are clamped to `len`, and reversed ranges are empty.
+pub fn clamp(r: &impl RangeBounds<usize>, len: usize) -> (usize, usize) {
+    let start = match r.start_bound() {
+        Bound::Included(b) => *b,
+        Bound::Excluded(b) => b.saturating_add(1),
+        Bound::Unbounded => 0,
+    }.min(len);
+    let end = match r.end_bound() {
+        Bound::Included(b) => b.saturating_add(1),
+        Bound::Excluded(b) => *b,
+        Bound::Unbounded => len,
+    }.min(len);
+    (start, end.max(start))
+}
+
+/// resolves `r` into a `(start, end)` pair, failing if it is reversed or
+/// does not fit within `0..=len`.
+pub fn check(r: &impl RangeBounds<usize>, len: usize) -> Result<(usize, usize)> {
+    let start = match r.start_bound() {
+        Bound::Included(b) => *b,
+        Bound::Excluded(b) => b.checked_add(1).ok_or(Error::OutOfBounds(*b))?,
+        Bound::Unbounded => 0,
+    };
+    let end = match r.end_bound() {
+        Bound::Included(b) => b.checked_add(1).ok_or(Error::OutOfBounds(*b))?,
+        Bound::Excluded(b) => *b,
+        Bound::Unbounded => len,
+    };
+
+    if start > end {
+        Err(Error::Reversed(start, end))
+    } else if end > len {
+        Err(Error::OutOfBounds(end))
+    } else {
+        Ok((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+    use crate::error::Error;
+    use crate::range::{clamp, check};
+
+    #[test]
+    fn test_clamp() {
+        assert_eq!(clamp(&(..), 5), (0, 5));
+        assert_eq!(clamp(&(1..=2), 5), (1, 3));
+        assert_eq!(clamp(&(3..10), 5), (3, 5));
+        assert_eq!(clamp(&(7..), 5), (5, 5));
+        assert_eq!(clamp(&(..=usize::MAX), 5), (0, 5));
+        assert_eq!(clamp(&(Bound::Excluded(1), Bound::Unbounded), 5), (2, 5));
+        assert_eq!(clamp(&(Bound::Included(4), Bound::Excluded(2)), 5), (4, 4));
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check(&(..), 5), Ok((0, 5)));
+        assert_eq!(check(&(1..=2), 5), Ok((1, 3)));
+        assert_eq!(check(&(5..), 5), Ok((5, 5)));
+        assert_eq!(check(&(3..10), 5), Err(Error::OutOfBounds(10)));
+        assert_eq!(check(&(7..), 5), Err(Error::Reversed(7, 5)));
+        assert_eq!(check(&(..=usize::MAX), 5), Err(Error::OutOfBounds(usize::MAX)));
+        assert_eq!(check(&(Bound::Excluded(1), Bound::Unbounded), 5), Ok((2, 5)));
+        assert_eq!(check(&(Bound::Included(4), Bound::Excluded(2)), 5),
+                   Err(Error::Reversed(4, 2)));
+    }
+}
@@ -1,11 +1,53 @@
 use std::rc::Rc;
-use std::cmp::min;
+use std::cell::Cell;
+use std::cmp::{min, Ordering};
 use std::cmp::PartialEq;
-use std::ops::{RangeBounds, Bound};
+use std::ops::{RangeBounds, Deref};
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::fmt;
+use std::str;
 use crate::newlines::count_newlines;
+use crate::error::{Error, Result};
+use crate::range;
+
+/// the longest string that is stored inline rather than on the heap.
+/// this is as much as fits in `Repr` without making it any larger.
+pub const INLINE_CAP: usize = 23;
+
+/// the storage backing an RcString
+#[derive(Clone, Debug)]
+enum Repr {
+    /// a shared heap allocated string
+    Shared(Rc<String>),
+    /// a short string stored without any allocation.
+    /// bytes past the end of the string are zero.
+    Inline([u8; INLINE_CAP]),
+    /// a string literal, which never needs to be copied
+    Static(&'static str),
+}
+
+impl Repr {
+    fn as_str(&self) -> &str {
+        match self {
+            Repr::Shared(s) => s,
+            // the zero padding is valid utf-8, so the whole array is too
+            Repr::Inline(buf) => unsafe { str::from_utf8_unchecked(buf) },
+            Repr::Static(s) => s,
+        }
+    }
+}
 
 /// RcString is a reference counted string with O(1) slice copies.
 ///
+/// the number of newlines in an RcString is counted at most once and then
+/// cached, so repeated calls to `lenlines` are O(1).
+///
+/// strings of up to `INLINE_CAP` bytes are stored inline, and string literals
+/// can be wrapped with `from_static`, so neither of these allocate.
+///
+/// RcString derefs to `str`, so it can be used anywhere a `&str` can.
+///
 /// # example
 /// ```
 /// use buffer::rcstring::RcString;
@@ -13,50 +55,159 @@ use crate::newlines::count_newlines;
 /// let rcs = RcString::from("hello world");
 /// assert_eq!(rcs.slice(..=4), RcString::from("hello"));
 /// assert_eq!(rcs.substr(0, 5), RcString::from("hello"));
+/// assert_eq!(rcs.find("world"), Some(6));
+/// assert!(rcs.starts_with("hello"));
 /// ```
 #[derive(Clone, Debug)]
 pub struct RcString {
-    base: Rc<String>,
+    base: Repr,
     off:  usize,
     len:  usize,
+    /// the number of newlines in the string, once it is known
+    nl:   Cell<Option<usize>>,
 }
 
 impl RcString {
     /// get the string that underlies the RcString.
     ///
-    /// this may be done with `Deref<T>` in the future
-    pub fn str<'a>(&'a self) -> &'a str {
-        &self.base[self.off..self.off+self.len]
+    /// this is the same as dereferencing the RcString.
+    pub fn str(&self) -> &str {
+        &self.base.as_str()[self.off..self.off+self.len]
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.base.as_str().as_bytes()[self.off..self.off+self.len]
+    }
+
+    /// wrap a string literal without copying it.
+    pub fn from_static(s: &'static str) -> Self {
+        RcString::new(Repr::Static(s), s.len())
+    }
+
+    fn new(base: Repr, len: usize) -> Self {
+        RcString {
+            base,
+            off: 0,
+            len,
+            nl:  Cell::new(None),
+        }
+    }
+
+    /// copy `s` into an inline RcString, if it is short enough.
+    fn inline(s: &str) -> Option<Self> {
+        RcString::inline_join(s, "")
+    }
+
+    fn inline_join(a: &str, b: &str) -> Option<Self> {
+        let len = a.len() + b.len();
+        if len > INLINE_CAP {
+            return None;
+        }
+        let mut buf = [0; INLINE_CAP];
+        buf[..a.len()].copy_from_slice(a.as_bytes());
+        buf[a.len()..len].copy_from_slice(b.as_bytes());
+        Some(RcString::new(Repr::Inline(buf), len))
+    }
+
+    /// copy the text of self into a string of its own, so that it no longer
+    /// keeps a larger shared string alive.
+    pub fn compact(&self) -> Self {
+        let rcs = RcString::from(self.str());
+        rcs.nl.set(self.nl.get());
+        rcs
+    }
+
+    /// the heap allocated string backing self, if there is one.
+    pub(crate) fn shared_base(&self) -> Option<&Rc<String>> {
+        match &self.base {
+            Repr::Shared(base) => Some(base),
+            _ => None,
+        }
+    }
+
+    /// join two RcStrings into a new one without allocating,
+    /// if the result is short enough to be stored inline.
+    pub fn join_inline(a: &Self, b: &Self) -> Option<Self> {
+        RcString::inline_join(a.str(), b.str())
     }
 
     /// get a substr starting at `off` with length `len`.
     ///
     /// this is has very little overhead and the result is Owned.
+    ///
+    /// offsets that fall inside of a character are moved back to the start
+    /// of that character.
     pub fn substr(&self, off: usize, len: usize) -> Self {
+        let end = off.saturating_add(len).min(self.len);
+        let off = self.floor_char_boundary(min(self.len, off));
+        let len = self.floor_char_boundary(end.max(off)) - off;
         RcString {
             base: self.base.clone(),
-            off: self.off + min(self.len, off),
-            len: if self.len >= off {
-                min(len, self.len - off)
-            } else {
-                0
-            },
+            off:  self.off + off,
+            len,
+            nl:   Cell::new(self.derive_lenlines(off, len)),
+        }
+    }
+
+    /// the closest char boundary at or before `idx`
+    fn floor_char_boundary(&self, mut idx: usize) -> usize {
+        let base = self.base.as_str();
+        while !base.is_char_boundary(self.off + idx) {
+            idx -= 1;
+        }
+        idx
+    }
+
+    /// work out the newline count of a substr from our own cached count,
+    /// when that means scanning less than the substr itself.
+    fn derive_lenlines(&self, off: usize, len: usize) -> Option<usize> {
+        let total = self.nl.get()?;
+        if len == self.len {
+            Some(total)
+        } else if self.len - len < len {
+            let b = self.bytes();
+            Some(total - count_newlines(&b[..off]) - count_newlines(&b[off+len..]))
+        } else {
+            None
         }
     }
 
     /// same as substr but uses `RangeBounds`
     pub fn slice(&self, r: impl RangeBounds<usize>) -> Self {
-        let start = match r.start_bound() {
-            Bound::Included(b) => *b,
-            Bound::Excluded(b) => b+1,
-            Bound::Unbounded => 0,
-        };
-        let len = match r.end_bound() {
-            Bound::Included(b) => (b - start) + 1,
-            Bound::Excluded(b) => b - start,
-            Bound::Unbounded => self.len - start,
-        };
-        self.substr(start, len)
+        let (start, end) = range::clamp(&r, self.len);
+        self.substr(start, end - start)
+    }
+
+    /// same as slice, but fails instead of clamping when `r` is out of
+    /// bounds, reversed, or does not fall on char boundaries.
+    pub fn try_slice(&self, r: impl RangeBounds<usize>) -> Result<Self> {
+        let (start, end) = range::check(&r, self.len)?;
+        if !self.is_char_boundary(start) {
+            Err(Error::NotCharBoundary(start))
+        } else if !self.is_char_boundary(end) {
+            Err(Error::NotCharBoundary(end))
+        } else {
+            Ok(self.substr(start, end - start))
+        }
+    }
+
+    /// same as try_slice, but None instead of an error, like `str::get`.
+    pub fn get(&self, r: impl RangeBounds<usize>) -> Option<Self> {
+        self.try_slice(r).ok()
+    }
+
+    /// split the RcString in two at byte offset `mid`, without copying.
+    /// None if `mid` is out of bounds or inside a character.
+    pub fn split_at(&self, mid: usize) -> Option<(Self, Self)> {
+        Some((self.get(..mid)?, self.get(mid..)?))
+    }
+
+    /// returns an iterator over the lines of self, as RcStrings sharing
+    /// the same base. lines are split the same way as `str::lines`.
+    pub fn lines(&self) -> Lines {
+        Lines {
+            rest: Some(self.clone()),
+        }
     }
 
     /// get the length of an RcString
@@ -64,9 +215,75 @@ impl RcString {
         self.len
     }
 
-    /// quickly count the number of lines in self
+    /// true if the RcString has a length of zero
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// get the number of newlines in self.
+    /// the first call scans the string, later calls are O(1).
     pub fn lenlines(&self) -> usize {
-        count_newlines(&self.base[self.off..(self.off+self.len)])
+        match self.nl.get() {
+            Some(nl) => nl,
+            None => {
+                let nl = count_newlines(self.bytes());
+                self.nl.set(Some(nl));
+                nl
+            },
+        }
+    }
+}
+
+/// an iterator over the lines of an RcString
+pub struct Lines {
+    rest: Option<RcString>,
+}
+
+impl Iterator for Lines {
+    type Item = RcString;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.take().filter(|r| !r.is_empty())?;
+        match rest.find('\n') {
+            Some(i) => {
+                let end = if rest[..i].ends_with('\r') { i - 1 } else { i };
+                self.rest = Some(rest.slice(i+1..));
+                Some(rest.slice(..end))
+            },
+            None => Some(rest),
+        }
+    }
+}
+
+impl Deref for RcString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.str()
+    }
+}
+
+impl AsRef<str> for RcString {
+    fn as_ref(&self) -> &str {
+        self.str()
+    }
+}
+
+impl Borrow<str> for RcString {
+    fn borrow(&self) -> &str {
+        self.str()
+    }
+}
+
+impl fmt::Display for RcString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.str(), f)
+    }
+}
+
+impl Hash for RcString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.str().hash(state)
     }
 }
 
@@ -76,32 +293,55 @@ impl PartialEq for RcString {
     }
 }
 
+impl Eq for RcString {}
+
+impl PartialEq<str> for RcString {
+    fn eq(&self, other: &str) -> bool {
+        self.str() == other
+    }
+}
+
+impl PartialEq<&str> for RcString {
+    fn eq(&self, other: &&str) -> bool {
+        self.str() == *other
+    }
+}
+
+impl PartialOrd for RcString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RcString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.str().cmp(other.str())
+    }
+}
+
 impl From<String> for RcString {
     fn from(s: String) -> Self {
-        let len = s.len();
-        RcString {
-            base: Rc::new(s),
-            off:  0,
-            len:  len,
-        }
+        RcString::inline(&s).unwrap_or_else(|| {
+            let len = s.len();
+            RcString::new(Repr::Shared(Rc::new(s)), len)
+        })
     }
 }
 
 impl From<&str> for RcString {
-    //TODO: RcString could have a special case for string references
     fn from(s: &str) -> Self {
-        let len = s.len();
-        RcString {
-            base: Rc::new(String::from(s)),
-            off:  0,
-            len:  len,
-        }
+        RcString::inline(s).unwrap_or_else(|| {
+            RcString::new(Repr::Shared(Rc::new(String::from(s))), s.len())
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rcstring::RcString;
+    use std::collections::{HashMap, BTreeSet};
+    use std::ops::Bound;
+    use crate::rcstring::{RcString, Repr, INLINE_CAP};
+    use crate::error::Error;
 
     #[test]
     fn test_new() {
@@ -134,6 +374,17 @@ mod tests {
         assert_eq!(rs3.slice(15..18).str(), "");
     }
 
+    #[test]
+    fn test_slice_clamped() {
+        let rs = RcString::from("añb");
+        assert_eq!(rs.slice((Bound::Included(3), Bound::Excluded(1))).str(), "");
+        assert_eq!(rs.slice(2..).str(), "ñb");
+        assert_eq!(rs.slice(..2).str(), "a");
+        assert_eq!(rs.substr(2, 1).str(), "ñ");
+        assert_eq!(rs.substr(2, 0).str(), "");
+        assert_eq!(rs.slice(..=usize::MAX).str(), "añb");
+    }
+
     #[test]
     fn test_len() {
         let rs = RcString::from("abcdefg");
@@ -157,4 +408,129 @@ mod tests {
         assert_eq!(rs3.lenlines(), 0);
         assert_eq!(rs3.substr(1,0).lenlines(), 0);
     }
+
+    #[test]
+    fn test_lenlines_cached() {
+        let rs = RcString::from("a\nb\nc\nd\ne\n");
+        assert_eq!(rs.lenlines(), 5);
+        // derived from the parent's count
+        assert_eq!(rs.substr(1, 8).lenlines(), 4);
+        assert_eq!(rs.substr(0, 9).lenlines(), 4);
+        assert_eq!(rs.slice(..).lenlines(), 5);
+        // counted from scratch
+        assert_eq!(rs.substr(4, 2).lenlines(), 1);
+        assert_eq!(rs.substr(4, 2).substr(0, 1).lenlines(), 0);
+    }
+
+    #[test]
+    fn test_inline() {
+        let short = "a".repeat(INLINE_CAP);
+        let long = "a".repeat(INLINE_CAP + 1);
+        assert!(matches!(RcString::from(short.as_str()).base, Repr::Inline(_)));
+        assert!(matches!(RcString::from(short.clone()).base, Repr::Inline(_)));
+        assert!(matches!(RcString::from(long.as_str()).base, Repr::Shared(_)));
+        assert_eq!(RcString::from(short.as_str()).str(), short);
+
+        let rs = RcString::from("ab\ncd");
+        assert_eq!(rs.substr(1, 3).str(), "b\nc");
+        assert_eq!(rs.substr(1, 3).lenlines(), 1);
+        assert_eq!(RcString::from("").str(), "");
+    }
+
+    #[test]
+    fn test_static() {
+        let rs = RcString::from_static("hello\nworld, this is a longer string");
+        assert!(matches!(rs.base, Repr::Static(_)));
+        assert_eq!(rs.slice(6..11).str(), "world");
+        assert_eq!(rs.lenlines(), 1);
+    }
+
+    #[test]
+    fn test_join_inline() {
+        let a = RcString::from("hello ");
+        let b = RcString::from("world").slice(..3);
+        assert_eq!(RcString::join_inline(&a, &b).unwrap().str(), "hello wor");
+        let long = RcString::from("a".repeat(INLINE_CAP).as_str());
+        assert!(RcString::join_inline(&a, &long).is_none());
+    }
+
+    #[test]
+    fn test_str_traits() {
+        let rs = RcString::from("hello wide world").slice(6..);
+        assert_eq!(rs.len(), 10);
+        assert_eq!(rs.to_uppercase(), "WIDE WORLD");
+        assert_eq!(rs.find('w'), Some(0));
+        assert_eq!(rs.rfind('w'), Some(5));
+        assert_eq!(format!("[{:>12}]", rs), "[  wide world]");
+        assert_eq!(rs, "wide world");
+
+        let mut map = HashMap::new();
+        map.insert(rs.clone(), 1);
+        assert_eq!(map.get("wide world"), Some(&1));
+
+        let set: BTreeSet<_> = vec!["b", "c", "a"].into_iter()
+            .map(RcString::from)
+            .collect();
+        let sorted: Vec<&str> = set.iter().map(|s| s.as_ref()).collect();
+        assert_eq!(sorted, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_get() {
+        let rs = RcString::from("añb");
+        assert_eq!(rs.get(..1).unwrap(), "a");
+        assert_eq!(rs.get(1..=2).unwrap(), "ñ");
+        assert_eq!(rs.get(..).unwrap(), "añb");
+        assert_eq!(rs.get(2..), None);
+        assert_eq!(rs.get(..2), None);
+        assert_eq!(rs.get((Bound::Included(3), Bound::Excluded(1))), None);
+        assert_eq!(rs.get(1..5), None);
+        assert_eq!(rs.get(..=usize::MAX), None);
+    }
+
+    #[test]
+    fn test_try_slice() {
+        let rs = RcString::from("añb");
+        assert_eq!(rs.try_slice(..1).unwrap(), "a");
+        assert_eq!(rs.try_slice(1..=2).unwrap(), "ñ");
+        assert_eq!(rs.try_slice(..).unwrap(), "añb");
+        assert_eq!(rs.try_slice(2..), Err(Error::NotCharBoundary(2)));
+        assert_eq!(rs.try_slice(..2), Err(Error::NotCharBoundary(2)));
+        assert_eq!(rs.try_slice((Bound::Included(3), Bound::Excluded(1))),
+                   Err(Error::Reversed(3, 1)));
+        assert_eq!(rs.try_slice(1..5), Err(Error::OutOfBounds(5)));
+        assert_eq!(rs.try_slice(..=usize::MAX), Err(Error::OutOfBounds(usize::MAX)));
+    }
+
+    #[test]
+    fn test_split_at() {
+        let rs = RcString::from("añb");
+        let (a, b) = rs.split_at(3).unwrap();
+        assert_eq!((a.str(), b.str()), ("añ", "b"));
+        let (a, b) = rs.split_at(0).unwrap();
+        assert_eq!((a.str(), b.str()), ("", "añb"));
+        assert_eq!(rs.split_at(2), None);
+    }
+
+    #[test]
+    fn test_lines() {
+        let rs = RcString::from("one\r\ntwo\n\nthree\r");
+        let lines: Vec<RcString> = rs.lines().collect();
+        assert_eq!(lines, vec!["one", "two", "", "three\r"]);
+        assert_eq!(RcString::from("a\n").lines().count(), 1);
+        assert_eq!(RcString::from("").lines().count(), 0);
+        assert_eq!(RcString::from("\n").lines().collect::<Vec<_>>(), vec![""]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let long = "a\n".repeat(100);
+        let rs = RcString::from(long.as_str()).slice(10..50);
+        assert_eq!(rs.lenlines(), 20);
+        let compact = rs.compact();
+        assert_eq!(compact, rs);
+        assert_eq!(compact.lenlines(), 20);
+        assert_eq!(compact.shared_base().unwrap().len(), 40);
+        assert!(matches!(rs.slice(..4).compact().base, Repr::Inline(_)));
+    }
 }
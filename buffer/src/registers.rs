@@ -0,0 +1,434 @@
+//! registers for yanked and deleted text, as in vim, and a kill ring, as in
+//! emacs.
+//!
+//! registers are shared by every buffer, so the editor keeps them and hands
+//! them to the buffer that yanks or pastes. they hold ropes, which are
+//! cheap to copy, along with how the text was selected, which decides how
+//! it is pasted.
+//!
+//! every yank and delete goes to the unnamed register and the kill ring.
+//! unless another register is named, a yank also goes to `0`, and a delete
+//! to `1`, shifting the older ones down to `9`.
+use std::collections::VecDeque;
+use std::ops::Range;
+use crate::Buffer;
+use crate::column::{char_width, str_width};
+use crate::rope::Rope;
+use crate::visual::VisualMode;
+
+/// the number of deletes kept in the numbered registers
+const NUMBERED: usize = 9;
+
+/// the most kills the kill ring holds, which is emacs's default
+pub const KILL_RING_MAX: usize = 120;
+
+/// some text, and how it was selected
+#[derive(Clone, Debug, PartialEq)]
+pub struct Register {
+    pub text: Rope,
+    pub kind: VisualMode,
+}
+
+impl Register {
+    pub fn new(text: Rope, kind: VisualMode) -> Self {
+        Register { text, kind }
+    }
+
+    /// this register with `other` added to its end. text added to or from
+    /// lines goes on a line of its own, and a block stays a block.
+    fn append(&self, other: &Register) -> Register {
+        let ends_line = self.text.chars_before(self.text.len()).next()
+            .is_none_or(|(_, ch)| ch == '\n');
+        let kind = match (self.kind, other.kind) {
+            (VisualMode::Block, _) | (_, VisualMode::Block) => VisualMode::Block,
+            (VisualMode::Line, _) | (_, VisualMode::Line) => VisualMode::Line,
+            _ => VisualMode::Char,
+        };
+        let mut text = self.text.clone();
+        if kind != VisualMode::Char && !ends_line {
+            text = Rope::concat(&text, &Rope::from("\n"));
+        }
+        text = Rope::concat(&text, &other.text);
+        if kind == VisualMode::Line && other.kind != VisualMode::Line {
+            text = Rope::concat(&text, &Rope::from("\n"));
+        }
+        Register { text, kind }
+    }
+}
+
+/// every register, and the kill ring
+#[derive(Clone, Debug, Default)]
+pub struct Registers {
+    named: [Option<Register>; 26],
+    unnamed: Option<Register>,
+    yanked: Option<Register>,
+    /// the numbered registers from `1`
+    deleted: VecDeque<Register>,
+    /// the newest kill first
+    ring: VecDeque<Register>,
+    /// the kill yank_pop last put in the text
+    ring_pos: usize,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Registers::default()
+    }
+
+    /// the register called `name`: `"` for the unnamed register, `a` to `z`
+    /// (or `A` to `Z`) for the named ones, and `0` to `9` for the numbered
+    /// ones.
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            '"' => self.unnamed.as_ref(),
+            '0' => self.yanked.as_ref(),
+            '1'..='9' => self.deleted.get(name as usize - '1' as usize),
+            'a'..='z' | 'A'..='Z' => {
+                self.named[name.to_ascii_lowercase() as usize - 'a' as usize].as_ref()
+            },
+            _ => None,
+        }
+    }
+
+    /// puts `reg` in the register called `name`. an uppercase name adds to
+    /// the end of the lowercase register instead of replacing it. returns
+    /// false if there is no such register that can be written.
+    pub fn set(&mut self, name: char, reg: Register) -> bool {
+        let reg = if name.is_ascii_uppercase() {
+            match self.get(name) {
+                Some(old) => old.append(&reg),
+                None => reg,
+            }
+        } else {
+            reg
+        };
+        match name {
+            '"' => self.unnamed = Some(reg),
+            'a'..='z' | 'A'..='Z' => {
+                self.named[name.to_ascii_lowercase() as usize - 'a' as usize] = Some(reg);
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// keeps yanked text in the register called `name`, or in `0` if there
+    /// is none.
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            Some(name) if self.set(name, reg.clone()) => {},
+            _ => self.yanked = Some(reg.clone()),
+        }
+        self.kill(reg);
+    }
+
+    /// keeps deleted text in the register called `name`, or in `1` if
+    /// there is none.
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            Some(name) if self.set(name, reg.clone()) => {},
+            _ => {
+                self.deleted.push_front(reg.clone());
+                self.deleted.truncate(NUMBERED);
+            },
+        }
+        self.kill(reg);
+    }
+
+    fn kill(&mut self, reg: Register) {
+        self.unnamed = Some(reg.clone());
+        self.ring.push_front(reg);
+        self.ring.truncate(KILL_RING_MAX);
+        self.ring_pos = 0;
+    }
+
+    /// the kills in the kill ring, newest first
+    pub fn kill_ring(&self) -> impl Iterator<Item = &Register> {
+        self.ring.iter()
+    }
+}
+
+impl Buffer {
+    /// yanks the visual selection into the register called `name`, like
+    /// vim's `y`. returns false outside of visual mode.
+    pub fn yank_into(&mut self, regs: &mut Registers, name: Option<char>) -> bool {
+        let kind = self.visual;
+        match (kind, self.visual_yank()) {
+            (Some(kind), Some(text)) => {
+                regs.yank(name, Register::new(text, kind));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// deletes the visual selection into the register called `name`, like
+    /// vim's `d`. returns false outside of visual mode.
+    pub fn delete_into(&mut self, regs: &mut Registers, name: Option<char>) -> bool {
+        let kind = self.visual;
+        match (kind, self.visual_delete()) {
+            (Some(kind), Some(text)) => {
+                regs.delete(name, Register::new(text, kind));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// changes the visual selection like visual_change, keeping the text
+    /// in the register called `name`. returns false outside of visual mode.
+    pub fn change_into(&mut self, regs: &mut Registers, name: Option<char>) -> bool {
+        let kind = self.visual;
+        match (kind, self.visual_change()) {
+            (Some(kind), Some(text)) => {
+                regs.delete(name, Register::new(text, kind));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// puts the text of `reg` after the cursor, like vim's `p`, or before
+    /// it, like `P`, as one undo step. charwise text goes after or before
+    /// the character under the cursor, leaving the cursor on its last
+    /// character. lines go below or above the cursor's line, and a block
+    /// goes into the lines from the cursor's down, at its column, with the
+    /// cursor on its first character.
+    pub fn paste(&mut self, reg: &Register, after: bool) {
+        let text: String = reg.text.str_iter().collect();
+        if text.is_empty() {
+            return;
+        }
+        let idx = self.cursor();
+        self.begin_group();
+        match reg.kind {
+            VisualMode::Char => {
+                let at_eol = self.text().chars_from(idx).next().is_none_or(|(_, ch)| ch == '\n');
+                let idx = if after && !at_eol { self.text().next_grapheme_boundary(idx) } else { idx };
+                let end = idx + text.len();
+                self.edit(idx, idx, &text, end);
+                let last = self.text().prev_grapheme_boundary(end);
+                self.set_cursor(last);
+            },
+            VisualMode::Line => {
+                let text = if text.ends_with('\n') { text } else { text + "\n" };
+                let lnum = if after { self.line + 1 } else { self.line };
+                if lnum > self.text().lenlines() {
+                    // below the last line, which has no newline to go after
+                    let len = self.text().len();
+                    self.edit(len, len, &format!("\n{}", &text[..text.len() - 1]), len);
+                } else {
+                    let at = self.text().line_start(lnum);
+                    self.edit(at, at, &text, at);
+                }
+                let idx = self.first_non_blank(lnum);
+                self.set_cursor(idx);
+            },
+            VisualMode::Block => self.paste_block(&text, after),
+        }
+        self.end_group();
+    }
+
+    fn paste_block(&mut self, text: &str, after: bool) {
+        let tab_width = self.tab_width;
+        let lines: Vec<_> = text.split('\n').collect();
+        let width = lines.iter().map(|l| str_width(l, 0, tab_width)).max().unwrap_or(0);
+        let idx = self.cursor();
+        let mut col = self.column();
+        if after {
+            if let Some((_, ch)) = self.text().chars_from(idx).next().filter(|&(_, ch)| ch != '\n') {
+                col += char_width(ch, col, tab_width).max(1);
+            }
+        }
+        let first = self.line;
+
+        // the block needs lines to go into, that reach its column
+        let missing = (first + lines.len() - 1).saturating_sub(self.text().lenlines());
+        if missing > 0 {
+            let len = self.text().len();
+            self.edit(len, len, &"\n".repeat(missing), len);
+        }
+        let rows: Vec<_> = (first..first + lines.len()).collect();
+        for &lnum in rows.iter().rev() {
+            let end = self.text().line_end(lnum);
+            let w = self.text().column_at(end, tab_width);
+            if w < col {
+                self.edit(end, end, &" ".repeat(col - w), end);
+            }
+        }
+
+        let at = self.cursors_at_column(&rows, col);
+        for (i, (&idx, line)) in at.iter().zip(&lines).enumerate().rev() {
+            // text after the block stays lined up
+            let line_end = self.text().line_end(rows[i]);
+            let pad = if idx < line_end { width - str_width(line, col, tab_width).min(width) } else { 0 };
+            let piece = format!("{}{}", line, " ".repeat(pad));
+            self.edit(idx, idx, &piece, idx);
+        }
+        self.set_cursor(at[0]);
+    }
+
+    /// inserts the newest kill at the cursor, like emacs's `C-y`, leaving
+    /// the cursor after it. returns false if the kill ring is empty.
+    pub fn kill_ring_yank(&mut self, regs: &mut Registers) -> bool {
+        regs.ring_pos = 0;
+        match regs.ring.front() {
+            Some(reg) => {
+                let text: String = reg.text.str_iter().collect();
+                let idx = self.cursor();
+                self.insert_yank(idx..idx, &text);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// replaces the text just put in by kill_ring_yank or yank_pop with the
+    /// next older kill, like emacs's `M-y`. returns false if the last thing
+    /// done to the text was not one of those.
+    pub fn yank_pop(&mut self, regs: &mut Registers) -> bool {
+        let range = match &self.last_yank {
+            Some((range, rev)) if *rev == self.current && !regs.ring.is_empty() => range.clone(),
+            _ => return false,
+        };
+        regs.ring_pos = (regs.ring_pos + 1) % regs.ring.len();
+        let text: String = regs.ring[regs.ring_pos].text.str_iter().collect();
+        self.insert_yank(range, &text);
+        true
+    }
+
+    fn insert_yank(&mut self, r: Range<usize>, text: &str) {
+        self.edit(r.start, r.end, text, r.start + text.len());
+        self.break_typing();
+        self.last_yank = Some((r.start..r.start + text.len(), self.current));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::registers::{Register, Registers};
+    use crate::rope::Rope;
+    use crate::visual::VisualMode;
+
+    fn reg(text: &str, kind: VisualMode) -> Register {
+        Register::new(Rope::from(text), kind)
+    }
+
+    fn text(regs: &Registers, name: char) -> Option<String> {
+        regs.get(name).map(|r| r.text.str_iter().collect())
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut regs = Registers::new();
+        assert!(regs.set('a', reg("one", VisualMode::Char)));
+        assert!(regs.set('A', reg("two", VisualMode::Char)));
+        assert_eq!(text(&regs, 'a'), Some("onetwo".to_string()));
+        assert!(regs.set('A', reg("three\n", VisualMode::Line)));
+        assert_eq!(regs.get('a'), Some(&reg("onetwo\nthree\n", VisualMode::Line)));
+        assert!(regs.set('B', reg("x", VisualMode::Char)));
+        assert_eq!(text(&regs, 'b'), Some("x".to_string()));
+        assert!(!regs.set('1', reg("x", VisualMode::Char)));
+        assert_eq!(regs.get('%'), None);
+
+        regs.yank(None, reg("y", VisualMode::Char));
+        assert_eq!(text(&regs, '0'), Some("y".to_string()));
+        for i in 0..10 {
+            regs.delete(None, reg(&i.to_string(), VisualMode::Char));
+        }
+        assert_eq!(text(&regs, '1'), Some("9".to_string()));
+        assert_eq!(text(&regs, '9'), Some("1".to_string()));
+        assert_eq!(text(&regs, '"'), Some("9".to_string()));
+        regs.yank(Some('c'), reg("z", VisualMode::Char));
+        assert_eq!(text(&regs, '0'), Some("y".to_string()));
+        assert_eq!(text(&regs, '"'), Some("z".to_string()));
+        assert_eq!(regs.kill_ring().count(), 12);
+    }
+
+    #[test]
+    fn test_into() {
+        let mut regs = Registers::new();
+        let mut b = Buffer::from(Rope::from("one two\nthree\n"));
+        assert!(!b.yank_into(&mut regs, None));
+        b.start_visual(VisualMode::Char);
+        b.move_right();
+        b.move_right();
+        assert!(b.yank_into(&mut regs, Some('a')));
+        assert_eq!(regs.get('a'), Some(&reg("one", VisualMode::Char)));
+
+        b.start_visual(VisualMode::Line);
+        assert!(b.delete_into(&mut regs, None));
+        assert_eq!(regs.get('1'), Some(&reg("one two\n", VisualMode::Line)));
+        assert_eq!(*b.text(), *"three\n");
+
+        b.start_visual(VisualMode::Char);
+        assert!(b.change_into(&mut regs, None));
+        assert_eq!(regs.get('1'), Some(&reg("t", VisualMode::Char)));
+        assert_eq!(regs.get('2'), Some(&reg("one two\n", VisualMode::Line)));
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut b = Buffer::from(Rope::from("abc"));
+        b.set_cursor(1);
+        b.paste(&reg("XY", VisualMode::Char), true);
+        assert_eq!(*b.text(), *"abXYc");
+        assert_eq!(b.cursor(), 3);
+        b.undo();
+        b.set_cursor(1);
+        b.paste(&reg("XY", VisualMode::Char), false);
+        assert_eq!(*b.text(), *"aXYbc");
+
+        let mut b = Buffer::from(Rope::from("a\n  b"));
+        b.paste(&reg("x\n", VisualMode::Line), true);
+        assert_eq!(*b.text(), *"a\nx\n  b");
+        assert_eq!(b.cursor(), 2);
+        b.paste(&reg("y", VisualMode::Line), false);
+        assert_eq!(*b.text(), *"a\ny\nx\n  b");
+        b.move_buffer_end();
+        b.paste(&reg(" z\n", VisualMode::Line), true);
+        assert_eq!(*b.text(), *"a\ny\nx\n  b\n z");
+        assert_eq!(b.cursor(), 11);
+        b.undo();
+        assert_eq!(*b.text(), *"a\ny\nx\n  b");
+    }
+
+    #[test]
+    fn test_paste_block() {
+        let mut b = Buffer::from(Rope::from("abc\nd\n"));
+        b.set_cursor(1);
+        b.paste(&reg("12\n3", VisualMode::Block), true);
+        assert_eq!(*b.text(), *"ab12c\nd 3\n");
+        assert_eq!(b.cursor(), 2);
+        b.undo();
+        assert_eq!(*b.text(), *"abc\nd\n");
+
+        let mut b = Buffer::from(Rope::from("ab"));
+        b.paste(&reg("x\ny\nz", VisualMode::Block), false);
+        assert_eq!(*b.text(), *"xab\ny\nz");
+    }
+
+    #[test]
+    fn test_kill_ring() {
+        let mut regs = Registers::new();
+        let mut b = Buffer::from(Rope::from("<>"));
+        assert!(!b.kill_ring_yank(&mut regs));
+        regs.yank(None, reg("a", VisualMode::Char));
+        regs.delete(None, reg("bb\n", VisualMode::Line));
+        regs.yank(None, reg("c", VisualMode::Char));
+
+        b.set_cursor(1);
+        assert!(b.kill_ring_yank(&mut regs));
+        assert_eq!(*b.text(), *"<c>");
+        assert!(b.yank_pop(&mut regs));
+        assert_eq!(*b.text(), *"<bb\n>");
+        assert!(b.yank_pop(&mut regs));
+        assert!(b.yank_pop(&mut regs));
+        assert_eq!(*b.text(), *"<c>");
+        assert_eq!(b.cursor(), 2);
+
+        b.insert_at_cursor("!");
+        assert!(!b.yank_pop(&mut regs));
+    }
+}
@@ -1,11 +1,15 @@
 //! implements a reference counted rope for efficently editing text.
 use std::rc::Rc;
 use std::ops::{RangeBounds, Bound};
-use std::cmp::PartialEq;
+use std::cmp::{max, PartialEq};
+use std::collections::{HashMap, HashSet};
 use std::vec::Vec;
 use std::option::Option;
 use std::str::Chars;
 use crate::rcstring::RcString;
+use crate::newlines::count_newlines;
+use crate::error::{Error, Result};
+use crate::range;
 
 /// The node of a Rope tree
 #[derive(Clone, Debug)]
@@ -14,12 +18,37 @@ pub struct Node {
     leftn:   usize,
     /// number of newlines in the left subtree
     leftnnl: usize,
+    /// number of bytes in the whole tree
+    len:     usize,
+    /// number of newlines in the whole tree
+    lines:   usize,
     /// the left subtree
     left:    Rope,
     /// the right subtree
     right:   Rope,
 }
 
+/// statistics about the memory used by a rope, from `Rope::memory_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// number of bytes of text in the rope
+    pub live_bytes:     usize,
+    /// number of bytes of heap allocated strings kept alive by the leaves.
+    /// each string is counted once, however many leaves share it.
+    pub retained_bytes: usize,
+    /// number of leaves in the rope
+    pub leaves:         usize,
+    /// number of nodes on the longest path from the root to a leaf
+    pub depth:          usize,
+    /// number of nodes that are also referenced from outside of the rope,
+    /// such as by an older version of it
+    pub shared_nodes:   usize,
+}
+
+/// `compact` copies leaves out of shared strings that the rope uses less
+/// than 1/COMPACT_RATIO of.
+const COMPACT_RATIO: usize = 2;
+
 /// Ropes are immutable data structures for representing a string.
 /// see [wikipedia](https://en.wikipedia.org/wiki/Rope_(data_structure))
 /// Ropes are reference counted and can be cloned with no additional memory,
@@ -31,37 +60,35 @@ pub enum Rope {
 }
 
 fn nth_line_idx(s: &str, lnum: usize) -> usize {
-    if s.len() == 0 {
+    if lnum == 0 {
         return 0;
     }
 
-    let mut n = lnum;
-    let mut r = 0;
-    for (i, ch) in s.chars().enumerate() {
-        if n == 0 {
-            return i;
-        }
-        if ch == '\n' {
-            n -= 1;
-        }
-        r = i;
-    }
-    return r + 1;
+    s.match_indices('\n')
+        .nth(lnum - 1)
+        .map_or(s.len(), |(i, _)| i + 1)
 }
 
 impl Rope {
 
+    /// creates a rope from a string literal without copying it.
+    pub fn from_static(s: &'static str) -> Self {
+        Rope::Leaf(RcString::from_static(s))
+    }
+
     /// concatenates two ropes into a new Rope.
     /// concat will clone one side if the other has zero length.
     pub fn concat(r1: &Self, r2: &Self) -> Self {
-        if r1.len() == 0 {
+        if r1.is_empty() {
             r2.clone()
-        } else if r2.len() == 0 {
+        } else if r2.is_empty() {
             r1.clone()
         } else {
             Rope::Node(Rc::new(Node {
                 leftn:   r1.len(),
                 leftnnl: r1.lenlines(),
+                len:     r1.len() + r2.len(),
+                lines:   r1.lenlines() + r2.lenlines(),
                 left:    r1.clone(),
                 right:   r2.clone(),
             }))
@@ -69,19 +96,25 @@ impl Rope {
     }
 
     /// gets the length of a rope in bytes.
-    /// note that this function is O(log n)
+    /// this function is O(1).
     pub fn len(&self) -> usize {
         match &self {
-            Rope::Node(nd) => nd.leftn + nd.right.len(),
+            Rope::Node(nd) => nd.len,
             Rope::Leaf(rcs) => rcs.len(),
         }
     }
 
+    /// true if the rope has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// gets the length of a rope in lines.
-    /// this function may need to scan some part of the rope to determine this.
+    /// this function is O(1), except that a leaf scans itself the first time
+    /// it is asked.
     pub fn lenlines(&self) -> usize {
         match &self {
-            Rope::Node(nd) => nd.leftnnl + nd.right.lenlines(),
+            Rope::Node(nd) => nd.lines,
             Rope::Leaf(rcs) => rcs.lenlines(),
         }
     }
@@ -93,7 +126,7 @@ impl Rope {
             Rope::Node(nd) =>
                 if idx >= nd.leftn {
                     nd.right.char_substr(idx-nd.leftn, n)
-                } else if idx + n <= nd.leftn {
+                } else if idx.saturating_add(n) <= nd.leftn {
                     nd.left.char_substr(idx, n)
                 } else {
                     Rope::concat(
@@ -103,26 +136,52 @@ impl Rope {
         }
     }
 
-    /// creates a new rope that is the slice with bounds `r`
+    /// creates a new rope that is the slice with bounds `r`.
+    /// out of bounds ranges are clamped to the rope.
     pub fn char_slice(&self, r: impl RangeBounds<usize>) -> Self {
-        let start = match r.start_bound() {
-            Bound::Included(b) => *b,
-            Bound::Excluded(b) => b+1,
-            Bound::Unbounded => 0,
-        };
-        let len = match r.end_bound() {
-            Bound::Included(b) => (b - start) + 1,
-            Bound::Excluded(b) => b - start,
-            Bound::Unbounded => self.len() - start,
-        };
-        self.char_substr(start, len)
+        let (start, end) = range::clamp(&r, self.len());
+        self.char_substr(start, end - start)
+    }
+
+    /// same as char_slice, but fails instead of clamping when `r` is out of
+    /// bounds, reversed, or does not fall on char boundaries.
+    pub fn try_slice(&self, r: impl RangeBounds<usize>) -> Result<Self> {
+        let (start, end) = self.check_range(&r)?;
+        Ok(self.char_substr(start, end - start))
+    }
+
+    /// true if `idx` is the start or end of a character in the rope.
+    pub fn is_char_boundary(&self, idx: usize) -> bool {
+        match &self {
+            Rope::Leaf(rcs) => rcs.is_char_boundary(idx),
+            Rope::Node(nd) => if idx <= nd.leftn {
+                nd.left.is_char_boundary(idx)
+            } else {
+                nd.right.is_char_boundary(idx - nd.leftn)
+            },
+        }
+    }
+
+    fn check_index(&self, idx: usize) -> Result<usize> {
+        if idx > self.len() {
+            Err(Error::OutOfBounds(idx))
+        } else if !self.is_char_boundary(idx) {
+            Err(Error::NotCharBoundary(idx))
+        } else {
+            Ok(idx)
+        }
+    }
+
+    pub(crate) fn check_range(&self, r: &impl RangeBounds<usize>) -> Result<(usize, usize)> {
+        let (start, end) = range::check(r, self.len())?;
+        Ok((self.check_index(start)?, self.check_index(end)?))
     }
 
     /// find the byte offset of the `lnum`th line.
     /// will return the length of the rope for lines past the last one.
     pub fn line_start(&self, lnum: usize) -> usize {
         match &self {
-            Rope::Leaf(rcs) => nth_line_idx(rcs.str(), lnum),
+            Rope::Leaf(rcs) => nth_line_idx(rcs, lnum),
             Rope::Node(nd) => if lnum <= nd.leftnnl {
                 nd.left.line_start(lnum)
             } else {
@@ -131,6 +190,31 @@ impl Rope {
         }
     }
 
+    /// find the byte offset of the end of the `lnum`th line, before its
+    /// newline. will return the length of the rope for the last line and
+    /// lines past it.
+    pub fn line_end(&self, lnum: usize) -> usize {
+        if lnum < self.lenlines() {
+            self.line_start(lnum + 1) - 1
+        } else {
+            self.len()
+        }
+    }
+
+    /// find the number of the line containing byte `idx`.
+    /// a newline is part of the line it ends.
+    pub fn line_at(&self, idx: usize) -> usize {
+        match &self {
+            Rope::Leaf(rcs) =>
+                count_newlines(&rcs.as_bytes()[..idx.min(rcs.len())]),
+            Rope::Node(nd) => if idx < nd.leftn {
+                nd.left.line_at(idx)
+            } else {
+                nd.right.line_at(idx - nd.leftn) + nd.leftnnl
+            }
+        }
+    }
+
     /// produces a Rope that is the substring of `n` lines starting at `idx`.
     /// a trailing newline will be included if it exists.
     pub fn line_substr(&self, idx: usize, n: usize) -> Self {
@@ -155,7 +239,7 @@ impl Rope {
     }
 
     /// returns an iterator over the leaves of the rope.
-    pub fn str_iter(&self) -> RopeIter<StrIter> {
+    pub fn str_iter(&self) -> RopeIter<'_, StrIter<'_>> {
         RopeIter {
             stack:    vec![self],
             curriter: None,
@@ -163,13 +247,62 @@ impl Rope {
     }
 
     /// returns an iterator over the characters of the rope.
-    pub fn char_iter(&self) -> RopeIter<CharIter> {
+    pub fn char_iter(&self) -> RopeIter<'_, CharIter<'_>> {
         RopeIter {
             stack:    vec![self],
             curriter: None,
         }
     }
 
+    /// returns an iterator over the characters of the rope starting at byte
+    /// `idx`, along with their byte offsets.
+    pub fn chars_from(&self, idx: usize) -> CharsFrom<'_> {
+        let pos = self.floor_char_boundary(idx);
+        let (leaf, start) = self.leaf_at(pos);
+        CharsFrom {
+            rope: self,
+            leaf,
+            start,
+            pos,
+        }
+    }
+
+    /// returns an iterator over the characters of the rope before byte `idx`
+    /// in reverse order, along with their byte offsets.
+    pub fn chars_before(&self, idx: usize) -> CharsBefore<'_> {
+        let pos = self.floor_char_boundary(idx);
+        let (leaf, start) = self.leaf_at(pos);
+        CharsBefore {
+            rope: self,
+            leaf,
+            start,
+            pos,
+        }
+    }
+
+    /// finds the leaf containing byte `idx`, and the offset of its start.
+    /// the end of the rope is in the last leaf.
+    pub(crate) fn leaf_at(&self, idx: usize) -> (&RcString, usize) {
+        match &self {
+            Rope::Leaf(rcs) => (rcs, 0),
+            Rope::Node(nd) => if idx < nd.leftn {
+                nd.left.leaf_at(idx)
+            } else {
+                let (leaf, start) = nd.right.leaf_at(idx - nd.leftn);
+                (leaf, start + nd.leftn)
+            }
+        }
+    }
+
+    /// the closest char boundary at or before `idx`, clamped to the rope.
+    pub(crate) fn floor_char_boundary(&self, idx: usize) -> usize {
+        let mut idx = idx.min(self.len());
+        while !self.is_char_boundary(idx) {
+            idx -= 1;
+        }
+        idx
+    }
+
     /// returns an iterator over the lines of the rope.
     /// newlines are considered line terminators, and will be included.
     pub fn line_iter(&self) -> LineIter {
@@ -183,6 +316,11 @@ impl Rope {
         let left = self.char_slice(..idx);
         let right = self.char_slice(idx..);
 
+        // short inserts (like typing) are joined onto the text before them
+        if let Some(left) = left.append_inline(&rope) {
+            return Rope::concat(&left, &right);
+        }
+
         //TODO: maybe use depth
         if left.len() > right.len() {
             Rope::concat(&left, &Rope::concat(&rope, &right))
@@ -191,26 +329,123 @@ impl Rope {
         }
     }
 
+    /// appends `rope` to the last leaf of self, if `rope` is a leaf and the
+    /// two together are short enough to be stored inline.
+    fn append_inline(&self, rope: &Self) -> Option<Self> {
+        match (self, rope) {
+            (Rope::Leaf(a), Rope::Leaf(b)) =>
+                RcString::join_inline(a, b).map(Rope::Leaf),
+            (Rope::Node(nd), Rope::Leaf(_)) =>
+                nd.right.append_inline(rope)
+                    .map(|right| Rope::concat(&nd.left, &right)),
+            _ => None,
+        }
+    }
+
+    /// same as insert, but fails if `idx` is out of bounds or not on a
+    /// char boundary.
+    pub fn try_insert(&self, idx: usize, rope: Self) -> Result<Self> {
+        Ok(self.insert(self.check_index(idx)?, rope))
+    }
+
     /// creates a new rope with range `r` deleted.
+    /// out of bounds ranges are clamped to the rope.
     pub fn delete(&self, r: impl RangeBounds<usize>) -> Self {
-        //TODO: this is bad
-        match (r.start_bound(), r.end_bound()) {
-            (Bound::Unbounded, Bound::Unbounded) => Rope::from(""),
-            (Bound::Unbounded, Bound::Included(hi)) =>
-                self.char_slice(hi+1..),
-            (Bound::Unbounded, Bound::Excluded(hi)) =>
-                self.char_slice(hi..),
-            (Bound::Included(lo), Bound::Unbounded) =>
-                self.char_slice(..lo),
-            (Bound::Included(lo), Bound::Included(hi)) =>
-                Rope::concat(
-                    &self.char_slice(..lo),
-                    &self.char_slice(hi+1..)),
-            (Bound::Included(lo), Bound::Excluded(hi)) =>
-                Rope::concat(
-                    &self.char_slice(..lo),
-                    &self.char_slice(hi..)),
-            (Bound::Excluded(_), _) => panic!(),
+        let (start, end) = range::clamp(&r, self.len());
+        Rope::concat(&self.char_slice(..start), &self.char_slice(end..))
+    }
+
+    /// same as delete, but fails instead of clamping when `r` is out of
+    /// bounds, reversed, or does not fall on char boundaries.
+    pub fn try_delete(&self, r: impl RangeBounds<usize>) -> Result<Self> {
+        let (start, end) = self.check_range(&r)?;
+        Ok(self.delete(start..end))
+    }
+
+    /// measures how much memory the rope is using, and how much of it is
+    /// held by strings that are no longer fully part of the text.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats {
+            live_bytes: self.len(),
+            ..MemoryStats::default()
+        };
+        let mut bases = HashSet::new();
+        let mut stack = vec![(self, 0)];
+
+        while let Some((rope, depth)) = stack.pop() {
+            stats.depth = max(stats.depth, depth);
+            match rope {
+                Rope::Node(nd) => {
+                    if Rc::strong_count(nd) > 1 {
+                        stats.shared_nodes += 1;
+                    }
+                    stack.push((&nd.right, depth + 1));
+                    stack.push((&nd.left, depth + 1));
+                },
+                Rope::Leaf(rcs) => {
+                    stats.leaves += 1;
+                    if let Some(base) = rcs.shared_base() {
+                        if bases.insert(Rc::as_ptr(base)) {
+                            stats.retained_bytes += base.capacity();
+                        }
+                    }
+                },
+            }
+        }
+        stats
+    }
+
+    /// creates a rope with the same text, where leaves that keep a mostly
+    /// unused shared string alive are copied into strings of their own.
+    /// parts of the rope that are already compact stay shared.
+    pub fn compact(&self) -> Self {
+        let mut used = HashMap::new();
+        self.for_each_leaf(&mut |rcs| {
+            if let Some(base) = rcs.shared_base() {
+                *used.entry(Rc::as_ptr(base)).or_insert(0) += rcs.len();
+            }
+        });
+
+        let mut sparse = HashSet::new();
+        self.for_each_leaf(&mut |rcs| {
+            if let Some(base) = rcs.shared_base() {
+                if used[&Rc::as_ptr(base)] * COMPACT_RATIO < base.capacity() {
+                    sparse.insert(Rc::as_ptr(base));
+                }
+            }
+        });
+
+        self.compact_leaves(&sparse).unwrap_or_else(|| self.clone())
+    }
+
+    fn for_each_leaf(&self, f: &mut impl FnMut(&RcString)) {
+        match self {
+            Rope::Node(nd) => {
+                nd.left.for_each_leaf(f);
+                nd.right.for_each_leaf(f);
+            },
+            Rope::Leaf(rcs) => f(rcs),
+        }
+    }
+
+    /// copies the leaves backed by a string in `sparse`.
+    /// returns None if nothing needed to be copied.
+    fn compact_leaves(&self, sparse: &HashSet<*const String>) -> Option<Self> {
+        match self {
+            Rope::Node(nd) => {
+                let left = nd.left.compact_leaves(sparse);
+                let right = nd.right.compact_leaves(sparse);
+                if left.is_none() && right.is_none() {
+                    None
+                } else {
+                    Some(Rope::concat(
+                        left.as_ref().unwrap_or(&nd.left),
+                        right.as_ref().unwrap_or(&nd.right)))
+                }
+            },
+            Rope::Leaf(rcs) => rcs.shared_base()
+                .filter(|base| sparse.contains(&Rc::as_ptr(base)))
+                .map(|_| Rope::Leaf(rcs.compact())),
         }
     }
 }
@@ -223,7 +458,7 @@ impl From<String> for Rope {
 
 impl From<&str> for Rope {
     fn from(s: &str) -> Self {
-        Rope::from(String::from(s))
+        Rope::Leaf(RcString::from(s))
     }
 }
 
@@ -238,7 +473,7 @@ impl PartialEq for Rope {
             }
         }
 
-        return true;
+        true
     }
 }
 
@@ -253,7 +488,7 @@ impl PartialEq<str> for Rope {
             }
         }
 
-        return true;
+        true
     }
 }
 
@@ -273,7 +508,7 @@ pub struct StrIter<'a> {
 
 impl<'a> LeafIter<'a> for StrIter<'a> {
     fn from(s: &'a RcString) -> Self {
-        StrIter{ s: Some(s.str()) }
+        StrIter{ s: Some(s) }
     }
 }
 
@@ -293,7 +528,7 @@ pub struct CharIter<'a> {
 
 impl<'a> LeafIter<'a> for CharIter<'a> {
     fn from(s: &'a RcString) -> Self {
-        CharIter{ it: s.str().chars()}
+        CharIter{ it: s.chars()}
     }
 }
 
@@ -333,12 +568,67 @@ impl<'a, T: LeafIter<'a>> Iterator for RopeIter<'a, T> {
                         },
                     }
                 }
-                return None
+                None
             }
         }
     }
 }
 
+/// an iterator over the characters of a rope and their byte offsets,
+/// from `Rope::chars_from`
+pub struct CharsFrom<'a> {
+    rope:  &'a Rope,
+    leaf:  &'a RcString,
+    start: usize,
+    pos:   usize,
+}
+
+impl<'a> Iterator for CharsFrom<'a> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.rope.len() {
+            return None;
+        }
+        if self.pos >= self.start + self.leaf.len() {
+            let (leaf, start) = self.rope.leaf_at(self.pos);
+            self.leaf = leaf;
+            self.start = start;
+        }
+        let ch = self.leaf[self.pos - self.start..].chars().next()?;
+        let pos = self.pos;
+        self.pos += ch.len_utf8();
+        Some((pos, ch))
+    }
+}
+
+/// a reverse iterator over the characters of a rope and their byte offsets,
+/// from `Rope::chars_before`
+pub struct CharsBefore<'a> {
+    rope:  &'a Rope,
+    leaf:  &'a RcString,
+    start: usize,
+    pos:   usize,
+}
+
+impl<'a> Iterator for CharsBefore<'a> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == 0 {
+            return None;
+        }
+        if self.pos <= self.start {
+            let (leaf, start) = self.rope.leaf_at(self.pos - 1);
+            self.leaf = leaf;
+            self.start = start;
+        }
+        let ch = self.leaf[..self.pos - self.start].chars().next_back()?;
+        self.pos -= ch.len_utf8();
+        Some((self.pos, ch))
+    }
+}
+
 pub struct LineIter {
     slice: Rope,
 }
@@ -356,7 +646,7 @@ impl Iterator for LineIter {
 
     fn next(&mut self) -> Option<Self::Item> {
         println!("slice: {:?})", self.slice);
-        if self.slice.len() == 0 {
+        if self.slice.is_empty() {
             return None
         }
         let line = self.slice.line_slice(0..1);
@@ -368,7 +658,9 @@ impl Iterator for LineIter {
 #[cfg(test)]
 mod tests {
     extern crate itertools;
-    use crate::rope::Rope;
+    use std::ops::Bound;
+    use crate::rope::{Rope, MemoryStats};
+    use crate::error::Error;
     use itertools::zip_eq;
 
     #[test]
@@ -405,6 +697,11 @@ mod tests {
         assert_eq!(r1.lenlines(), 4);
         assert_eq!(Rope::from("").lenlines(), 0);
         assert_eq!(Rope::from("\n").lenlines(), 1);
+
+        let r2 = r1.insert(5, Rope::from("\n\n")).delete(..4);
+        assert_eq!(&r2, "b\n\n\nb\nbccc\n");
+        assert_eq!(r2.lenlines(), 5);
+        assert_eq!(r2.len(), 11);
     }
 
     #[test]
@@ -464,6 +761,68 @@ mod tests {
         assert_eq!(r4.line_start(0), 0);
     }
 
+    #[test]
+    fn test_line_start_multibyte() {
+        let r1 = Rope::concat(&Rope::from("ñé\nü"), &Rope::from("\nx"));
+        assert_eq!(r1.line_start(1), 5);
+        assert_eq!(r1.line_start(2), 8);
+        assert_eq!(r1.line_start(3), 9);
+    }
+
+    #[test]
+    fn test_line_end() {
+        let r1 = Rope::concat(&Rope::from("aa\n"), &Rope::from("\nb"));
+        assert_eq!(r1.line_end(0), 2);
+        assert_eq!(r1.line_end(1), 3);
+        assert_eq!(r1.line_end(2), 5);
+        assert_eq!(r1.line_end(3), 5);
+    }
+
+    #[test]
+    fn test_line_at() {
+        let r1 = Rope::concat(
+            &Rope::from("aa\na"),
+            &Rope::concat(
+                &Rope::from("\nbbb\n"),
+                &Rope::from("ccc")));
+
+        assert_eq!(r1.line_at(0), 0);
+        assert_eq!(r1.line_at(2), 0);
+        assert_eq!(r1.line_at(3), 1);
+        assert_eq!(r1.line_at(4), 1);
+        assert_eq!(r1.line_at(5), 2);
+        assert_eq!(r1.line_at(9), 3);
+        assert_eq!(r1.line_at(12), 3);
+        assert_eq!(r1.line_at(100), 3);
+    }
+
+    #[test]
+    fn test_chars_from() {
+        let r1 = Rope::concat(
+            &Rope::from("añ"),
+            &Rope::concat(&Rope::from("b"), &Rope::from("çd")));
+
+        let chars: Vec<_> = r1.chars_from(1).collect();
+        assert_eq!(chars, vec![(1, 'ñ'), (3, 'b'), (4, 'ç'), (6, 'd')]);
+        // offsets inside of a character start from that character
+        assert_eq!(r1.chars_from(5).next(), Some((4, 'ç')));
+        assert_eq!(r1.chars_from(7).next(), None);
+        assert_eq!(r1.chars_from(70).next(), None);
+    }
+
+    #[test]
+    fn test_chars_before() {
+        let r1 = Rope::concat(
+            &Rope::from("añ"),
+            &Rope::concat(&Rope::from("b"), &Rope::from("çd")));
+
+        let chars: Vec<_> = r1.chars_before(6).collect();
+        assert_eq!(chars, vec![(4, 'ç'), (3, 'b'), (1, 'ñ'), (0, 'a')]);
+        assert_eq!(r1.chars_before(70).next(), Some((6, 'd')));
+        assert_eq!(r1.chars_before(0).next(), None);
+        assert_eq!(Rope::from("").chars_before(0).next(), None);
+    }
+
     #[test]
     fn test_line_substr() {
         let r1 = Rope::concat(
@@ -539,6 +898,18 @@ mod tests {
         assert_eq!(&r1.insert(11, Rope::from(".")), "hello world.");
     }
 
+    #[test]
+    fn test_insert_typing() {
+        let mut r1 = Rope::from_static("fn main() {\n    \n}\n");
+        for (i, ch) in "let x = 1;".chars().enumerate() {
+            r1 = r1.insert(16 + i, Rope::from(ch.to_string()));
+        }
+        assert_eq!(&r1, "fn main() {\n    let x = 1;\n}\n");
+        // the typed characters share one leaf
+        assert_eq!(r1.str_iter().count(), 3);
+        assert_eq!(r1.lenlines(), 3);
+    }
+
     #[test]
     fn test_delete() {
         let r1 = Rope::concat(
@@ -551,5 +922,74 @@ mod tests {
         assert_eq!(&r1.delete(1..=4), "abccc");
         assert_eq!(&r1.delete(1..5), "abccc");
         assert_eq!(&r1.delete(4..), "aaab");
+        assert_eq!(&r1.delete((Bound::Excluded(0), Bound::Included(4))), "abccc");
+        assert_eq!(&r1.delete(7..20), "aaabbbc");
+    }
+
+    #[test]
+    fn test_try_slice() {
+        let r1 = Rope::concat(&Rope::from("añ"), &Rope::from("b"));
+        assert_eq!(&r1.try_slice(1..).unwrap(), "ñb");
+        assert_eq!(&r1.try_slice(3..4).unwrap(), "b");
+        assert_eq!(r1.try_slice(2..), Err(Error::NotCharBoundary(2)));
+        assert_eq!(r1.try_slice(..5), Err(Error::OutOfBounds(5)));
+        assert_eq!(r1.try_slice((Bound::Included(3), Bound::Excluded(1))),
+                   Err(Error::Reversed(3, 1)));
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let r1 = Rope::from("añb");
+        assert_eq!(&r1.try_insert(3, Rope::from("x")).unwrap(), "añxb");
+        assert_eq!(&r1.try_insert(4, Rope::from("x")).unwrap(), "añbx");
+        assert_eq!(r1.try_insert(2, Rope::from("x")), Err(Error::NotCharBoundary(2)));
+        assert_eq!(r1.try_insert(5, Rope::from("x")), Err(Error::OutOfBounds(5)));
+    }
+
+    #[test]
+    fn test_try_delete() {
+        let r1 = Rope::concat(&Rope::from("añ"), &Rope::from("bc"));
+        assert_eq!(&r1.try_delete(1..3).unwrap(), "abc");
+        assert_eq!(&r1.try_delete((Bound::Excluded(0), Bound::Unbounded)).unwrap(), "a");
+        assert_eq!(r1.try_delete(..2), Err(Error::NotCharBoundary(2)));
+        assert_eq!(r1.try_delete(4..9), Err(Error::OutOfBounds(9)));
+        assert_eq!(r1.try_delete((Bound::Included(3), Bound::Excluded(1))),
+                   Err(Error::Reversed(3, 1)));
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let text = "x".repeat(1000);
+        let r1 = Rope::from(text.clone());
+        let r2 = r1.delete(100..900);
+
+        let stats = Rope::concat(&r2, &Rope::from("tail")).memory_stats();
+        assert_eq!(stats, MemoryStats {
+            live_bytes:     204,
+            retained_bytes: text.capacity(),
+            leaves:         3,
+            depth:          2,
+            shared_nodes:   1,
+        });
+    }
+
+    #[test]
+    fn test_compact() {
+        let r1 = Rope::from("x".repeat(1000));
+        let r2 = Rope::concat(
+            &r1.delete(100..950),
+            &Rope::from("y".repeat(100)));
+        assert_eq!(r2.memory_stats().retained_bytes, 1100);
+
+        let r3 = r2.compact();
+        assert_eq!(r3, r2);
+        let stats = r3.memory_stats();
+        assert_eq!(stats.live_bytes, 250);
+        assert_eq!(stats.retained_bytes, 250);
+        assert_eq!(stats.leaves, 3);
+
+        // already compact ropes are left alone
+        assert_eq!(r3.compact().memory_stats().retained_bytes, 250);
+        assert_eq!(r1.compact().memory_stats().retained_bytes, 1000);
     }
 }
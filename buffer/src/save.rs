@@ -0,0 +1,306 @@
+//! writes a buffer back to its file.
+//!
+//! the text is written to a temporary file in the same directory, synced,
+//! and renamed over the old file, so a crash in the middle of saving leaves
+//! either the old file or the new one, never a truncated mix.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{Buffer, no_file_error};
+use crate::hash::hash_rope;
+use crate::rope::Rope;
+
+/// whether a copy of the old file is kept when a buffer is saved over it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backup {
+    None,
+    /// `name~`, replaced on every save
+    Tilde,
+    /// `name.YYYYMMDD-HHMMSS~` with the time of the save in UTC, so every
+    /// save keeps its own copy
+    Timestamped,
+}
+
+/// the year, month and day of the date `days` after 1970-01-01.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// where `backup` keeps the copy of the file at `path` saved over at `now`
+pub fn backup_path(path: &Path, backup: Backup, now: SystemTime) -> Option<PathBuf> {
+    let mut name = path.file_name()?.to_os_string();
+    match backup {
+        Backup::None => return None,
+        Backup::Tilde => name.push("~"),
+        Backup::Timestamped => {
+            let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let (year, month, day) = civil_date(secs / 86_400);
+            let secs = secs % 86_400;
+            name.push(format!(".{:04}{:02}{:02}-{:02}{:02}{:02}~", year, month, day,
+                              secs / 3600, secs / 60 % 60, secs % 60));
+        },
+    }
+    Some(path.with_file_name(name))
+}
+
+/// gives `file` the permissions and, where allowed, the owner of the file
+/// described by `meta`.
+fn copy_metadata(file: &File, path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    // chown clears the setuid and setgid bits, so it has to come first
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{chown, MetadataExt};
+        // only root can give a file away, but the group can often be kept
+        if chown(path, Some(meta.uid()), Some(meta.gid())).is_err() {
+            let _ = chown(path, None, Some(meta.gid()));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    file.set_permissions(meta.permissions())
+}
+
+/// writes `text` to a new file in the directory of `path`, with the
+/// metadata of the file that is already there, if any.
+fn write_temp(path: &Path, text: &Rope, old: Option<&fs::Metadata>) -> io::Result<PathBuf> {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")
+    })?);
+    name.push(format!(".{}.tmp", process::id()));
+    let temp = path.with_file_name(name);
+
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(&temp)?;
+    let result = (|| {
+        let mut w = BufWriter::new(&file);
+        for chunk in text.str_iter() {
+            w.write_all(chunk.as_bytes())?;
+        }
+        w.flush()?;
+        drop(w);
+        if let Some(meta) = old {
+            copy_metadata(&file, &temp, meta)?;
+        }
+        file.sync_all()
+    })();
+
+    match result {
+        Ok(()) => Ok(temp),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        },
+    }
+}
+
+/// replaces the file at `path` with `text` atomically, keeping a copy of
+/// the old file as `backup` says.
+fn write_atomic(path: &Path, text: &Rope, backup: Backup) -> io::Result<()> {
+    // saving through a symlink replaces the file it points to
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let old = fs::metadata(&path).ok();
+
+    let temp = write_temp(&path, text, old.as_ref())?;
+    let result = (|| {
+        if old.is_some() {
+            if let Some(copy) = backup_path(&path, backup, SystemTime::now()) {
+                fs::copy(&path, copy)?;
+            }
+        }
+        fs::rename(&temp, &path)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    // make the rename itself durable. not every platform can sync a
+    // directory, and the file is already saved, so this can't fail the save
+    if let Some(dir) = path.parent() {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+impl Buffer {
+    /// the file the buffer is loaded from and saved to
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// true if the text has changed since it was last loaded or saved.
+    /// undoing back to the saved revision makes the buffer unmodified again.
+    pub fn is_modified(&self) -> bool {
+        self.saved != Some(self.current)
+    }
+
+    /// the hash of the file's contents when the buffer last loaded or saved
+    /// it, or None if it has done neither.
+    pub fn disk_hash(&self) -> Option<u64> {
+        self.disk.map(|disk| disk.hash)
+    }
+
+    /// sets how a copy of the old file is kept on save
+    pub fn set_backup(&mut self, backup: Backup) {
+        self.backup = backup;
+    }
+
+    /// writes the text to the buffer's file, along with its undo file.
+    pub fn save(&mut self) -> io::Result<()> {
+        let name = self.filename.clone().ok_or_else(no_file_error)?;
+        self.save_as(name)
+    }
+
+    /// writes the text to the file `name`, which becomes the buffer's file.
+    pub fn save_as(&mut self, name: String) -> io::Result<()> {
+        write_atomic(Path::new(&name), self.text(), self.backup)?;
+        self.filename = Some(name);
+        self.saved = Some(self.current);
+        self.edits = 0;
+        self.changed = None;
+        self.record_disk(hash_rope(self.text()));
+        self.start_swap(&self.text().clone());
+        // the text is safe, losing the history is not worth failing over
+        let _ = self.write_undo_file();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::Buffer;
+    use crate::hash::hash_chunks;
+    use crate::rope::Rope;
+    use crate::save::{Backup, backup_path, civil_date};
+    use crate::testutil::TempDir;
+    use crate::undofile::undo_path;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(59), (1970, 3, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(20_744), (2026, 10, 18));
+    }
+
+    #[test]
+    fn test_backup_path() {
+        let now = UNIX_EPOCH + Duration::from_secs(20_744 * 86_400 + 3723);
+        let path = Path::new("/a/b.txt");
+        assert_eq!(backup_path(path, Backup::None, now), None);
+        assert_eq!(backup_path(path, Backup::Tilde, now), Some(PathBuf::from("/a/b.txt~")));
+        assert_eq!(backup_path(path, Backup::Timestamped, now),
+                   Some(PathBuf::from("/a/b.txt.20261018-010203~")));
+    }
+
+    #[test]
+    fn test_save() {
+        let tmp = TempDir::new("save-save");
+        let dir = tmp.path();
+        let path = dir.join("f.txt");
+        let name = path.to_str().unwrap().to_string();
+
+        let mut b = Buffer::from(Rope::from("one\n"));
+        assert!(b.save().is_err());
+        b.save_as(name.clone()).unwrap();
+        assert_eq!(b.filename(), Some(&*name));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+
+        b.set_backup(Backup::Tilde);
+        b.move_buffer_end();
+        b.insert_at_cursor("two\n");
+        b.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert_eq!(fs::read_to_string(dir.join("f.txt~")).unwrap(), "one\n");
+        assert_eq!(entries(dir), vec![".f.txt.swp", ".f.txt.un~", "f.txt", "f.txt~"]);
+        assert!(undo_path(&path).exists());
+
+        // the history is restored from the undo file written on save
+        let mut c = Buffer::new(Some(name)).unwrap();
+        assert!(c.undo());
+        assert_eq!(*c.text(), *"one\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_permissions() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let tmp = TempDir::new("save-perms");
+        let dir = tmp.path();
+        let path = dir.join("f.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o4751)).unwrap();
+        let link = dir.join("link");
+        symlink(&path, &link).unwrap();
+
+        let mut b = Buffer::new(Some(link.to_str().unwrap().to_string())).unwrap();
+        b.insert_at_cursor("new ");
+        b.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new old");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o4751);
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn test_is_modified() {
+        let tmp = TempDir::new("save-modified");
+        let dir = tmp.path();
+        let path = dir.join("f.txt");
+        fs::write(&path, "abc").unwrap();
+
+        let mut b = Buffer::new(Some(path.to_str().unwrap().to_string())).unwrap();
+        assert!(!b.is_modified());
+        assert_eq!(b.disk_hash(), Some(hash_chunks(vec![&b"abc"[..]])));
+        b.insert_at_cursor("x");
+        assert!(b.is_modified());
+        b.undo();
+        assert!(!b.is_modified());
+        b.redo();
+        b.save().unwrap();
+        assert!(!b.is_modified());
+        assert_eq!(b.disk_hash(), Some(hash_chunks(vec![&b"xabc"[..]])));
+
+        // typing straight after saving does not change the saved revision
+        b.move_buffer_end();
+        b.insert_at_cursor("y");
+        b.save().unwrap();
+        b.insert_at_cursor("z");
+        assert!(b.is_modified());
+        b.undo();
+        assert!(!b.is_modified());
+        assert_eq!(*b.text(), *"xabcy");
+
+        let mut c = Buffer::from(Rope::from(""));
+        assert!(!c.is_modified());
+        assert_eq!(c.disk_hash(), None);
+        c.insert_at_cursor("z");
+        assert!(c.is_modified());
+    }
+}
@@ -0,0 +1,509 @@
+//! searching the text for a regex, as in vim.
+//!
+//! while a pattern is being typed the cursor previews the match it would
+//! go to, and once the editor says what region it shows, the matches in it
+//! are kept up to date for highlighting. `search_next` and `search_prev`
+//! then go through the matches in the whole text, and say which one the
+//! cursor is on.
+use std::mem;
+use std::ops::Range;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind};
+use crate::Buffer;
+use crate::boundary::Direction;
+use crate::rope::Rope;
+use crate::selection::shift;
+
+/// whether a search pattern matches letters of the other case
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// insensitive, unless the pattern has an uppercase letter in it
+    Smart,
+}
+
+/// a match a search went to: the `index`th of `total` in the text,
+/// counting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub range: Range<usize>,
+    pub index: usize,
+    pub total: usize,
+}
+
+/// the search state of a buffer
+#[derive(Clone, Debug)]
+pub struct Search {
+    pattern: String,
+    /// the compiled pattern, if it is not empty or invalid
+    regex: Option<Regex>,
+    direction: Direction,
+    case: CaseMode,
+    wrap: bool,
+    /// the cursor and pattern from before the pattern being typed
+    typing: Option<(usize, String)>,
+    /// the region to find matches in, if the editor has set one
+    visible: Option<Range<usize>>,
+    matches: Vec<Range<usize>>,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            pattern: String::new(),
+            regex: None,
+            direction: Direction::Forward,
+            case: CaseMode::Smart,
+            wrap: true,
+            typing: None,
+            visible: None,
+            matches: vec![],
+        }
+    }
+}
+
+/// whether `pattern` has an uppercase letter, not counting escapes like
+/// `\S`.
+fn has_upper(pattern: &str) -> bool {
+    let mut escaped = false;
+    for ch in pattern.chars() {
+        if !escaped && ch.is_uppercase() {
+            return true;
+        }
+        escaped = !escaped && ch == '\\';
+    }
+    false
+}
+
+/// about how many bytes of text are searched at once
+const CHUNK: usize = 1 << 16;
+
+/// whether `hir` can match a newline, so that its matches can run on from
+/// one line to the next
+fn has_newline(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Literal(lit) => lit.0.contains(&b'\n'),
+        HirKind::Class(Class::Unicode(class)) => {
+            class.ranges().iter().any(|r| r.start() <= '\n' && '\n' <= r.end())
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            class.ranges().iter().any(|r| r.start() <= b'\n' && b'\n' <= r.end())
+        }
+        HirKind::Repetition(rep) => has_newline(&rep.sub),
+        HirKind::Capture(cap) => has_newline(&cap.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(has_newline),
+        HirKind::Empty | HirKind::Look(_) => false,
+    }
+}
+
+/// calls `f` with every match of `regex` in bytes `range` of `text`, in
+/// order. the text is searched a piece of whole lines at a time, rather
+/// than copied out all at once, unless the pattern can match a newline and
+/// so a match could span pieces.
+pub(crate) fn each_match(text: &Rope, regex: &Regex, range: Range<usize>,
+                         mut f: impl FnMut(Range<usize>)) {
+    let whole = regex_syntax::parse(regex.as_str()).map_or(true, |hir| has_newline(&hir));
+    let mut start = range.start;
+    loop {
+        let stop = match start.checked_add(CHUNK).filter(|&idx| idx < range.end && !whole) {
+            Some(idx) => text.line_start(text.line_at(idx) + 1).min(range.end),
+            None => range.end,
+        };
+        let s: String = text.char_slice(start..stop).str_iter().collect();
+        for m in regex.find_iter(&s) {
+            // an empty match at the end is found again at the next start
+            if m.start() < s.len() || stop == range.end {
+                f(start + m.start()..start + m.end());
+            }
+        }
+        if stop >= range.end {
+            return;
+        }
+        start = stop;
+    }
+}
+
+pub(crate) fn compile(pattern: &str, case: CaseMode) -> Result<Option<Regex>, regex::Error> {
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+    let insensitive = match case {
+        CaseMode::Sensitive => false,
+        CaseMode::Insensitive => true,
+        CaseMode::Smart => !has_upper(pattern),
+    };
+    RegexBuilder::new(pattern)
+        .case_insensitive(insensitive)
+        .multi_line(true)
+        .build()
+        .map(Some)
+}
+
+impl Buffer {
+    /// the last search pattern
+    pub fn search_pattern(&self) -> &str {
+        &self.search.pattern
+    }
+
+    /// the direction `search_next` goes in
+    pub fn search_direction(&self) -> Direction {
+        self.search.direction
+    }
+
+    pub fn search_case(&self) -> CaseMode {
+        self.search.case
+    }
+
+    pub fn set_search_case(&mut self, case: CaseMode) {
+        self.search.case = case;
+        let pattern = self.search.pattern.clone();
+        let _ = self.compile_search(&pattern);
+    }
+
+    /// whether searches go on from the other end of the text
+    pub fn search_wrap(&self) -> bool {
+        self.search.wrap
+    }
+
+    pub fn set_search_wrap(&mut self, wrap: bool) {
+        self.search.wrap = wrap;
+    }
+
+    /// starts typing a pattern to search for in `direction`, like vim's `/`
+    /// or `?`.
+    pub fn start_search(&mut self, direction: Direction) {
+        let old = self.search.pattern.clone();
+        self.search.typing = Some((self.cursor(), old));
+        self.search.direction = direction;
+        let _ = self.compile_search("");
+    }
+
+    /// sets the pattern to search for. while one is being typed, the cursor
+    /// is moved to the match it would find from where the search started,
+    /// which is returned. an invalid pattern, such as one only partly
+    /// typed, matches nothing.
+    pub fn set_search_pattern(&mut self, pattern: &str) -> Result<Option<SearchMatch>, regex::Error> {
+        let compiled = self.compile_search(pattern);
+        let origin = match &self.search.typing {
+            Some((origin, _)) => *origin,
+            None => return compiled.map(|_| None),
+        };
+        let found = self.find_match(origin, self.search.direction);
+        self.move_cursor(found.as_ref().map_or(origin, |m| m.range.start));
+        compiled.map(|_| found)
+    }
+
+    /// stops typing the pattern, leaving the cursor at its match. where the
+    /// search started is added to the jump list.
+    pub fn finish_search(&mut self) {
+        if let Some((origin, _)) = self.search.typing.take() {
+            let here = self.cursor();
+            if here != origin {
+                self.move_cursor(origin);
+                self.record_jump();
+                self.move_cursor(here);
+            }
+        }
+    }
+
+    /// stops typing the pattern, putting the cursor and the pattern back to
+    /// how they were.
+    pub fn cancel_search(&mut self) {
+        if let Some((origin, old)) = self.search.typing.take() {
+            let _ = self.compile_search(&old);
+            self.move_cursor(origin);
+        }
+    }
+
+    /// whether a pattern is being typed
+    pub fn is_searching(&self) -> bool {
+        self.search.typing.is_some()
+    }
+
+    /// goes to the next match in the search direction, as a jump, like
+    /// vim's `n`.
+    pub fn search_next(&mut self) -> Option<SearchMatch> {
+        self.search_go(self.search.direction)
+    }
+
+    /// goes to the next match against the search direction, like vim's `N`.
+    pub fn search_prev(&mut self) -> Option<SearchMatch> {
+        let dir = match self.search.direction {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        };
+        self.search_go(dir)
+    }
+
+    /// sets the region the editor shows, so only matches in it are kept by
+    /// `search_matches`. the region is widened to whole lines. until this is
+    /// called no matches are kept at all.
+    pub fn set_search_region(&mut self, visible: Range<usize>) {
+        self.search.visible = Some(visible);
+        self.update_matches();
+    }
+
+    /// the matches of the search pattern in the visible region, in order
+    pub fn search_matches(&self) -> &[Range<usize>] {
+        &self.search.matches
+    }
+
+    /// moves the visible region through the replacement of bytes
+    /// `start..end` with `len` new ones, and finds the matches on the lines
+    /// it changed again.
+    pub(crate) fn shift_search(&mut self, start: usize, end: usize, len: usize) {
+        let changed = self.move_search(start, end, len);
+        self.rescan_matches(changed);
+    }
+
+    /// moves the visible region and its matches through the replacement of
+    /// bytes `start..end` with `len` new ones, dropping the matches it
+    /// touched. returns the bytes whose matches have to be found again.
+    pub(crate) fn move_search(&mut self, start: usize, end: usize, len: usize) -> Range<usize> {
+        let mut changed = start..start + len;
+        let visible = match &mut self.search.visible {
+            Some(visible) => visible,
+            None => return changed,
+        };
+        *visible = shift(visible.start, start, end, len)..shift(visible.end, start, end, len);
+        for m in mem::take(&mut self.search.matches) {
+            let moved = shift(m.start, start, end, len)..shift(m.end, start, end, len);
+            if m.end < start || m.start > end {
+                self.search.matches.push(moved);
+            } else {
+                changed = changed.start.min(moved.start)..changed.end.max(moved.end);
+            }
+        }
+        changed
+    }
+
+    fn search_go(&mut self, dir: Direction) -> Option<SearchMatch> {
+        let found = self.find_match(self.cursor(), dir)?;
+        self.record_jump();
+        self.set_cursor(found.range.start);
+        Some(found)
+    }
+
+    /// compiles `pattern` as the search pattern, which matches nothing if
+    /// it is invalid.
+    fn compile_search(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.search.pattern = pattern.to_string();
+        let compiled = compile(pattern, self.search.case);
+        self.search.regex = compiled.as_ref().ok().cloned().flatten();
+        self.update_matches();
+        compiled.map(|_| ())
+    }
+
+    fn update_matches(&mut self) {
+        self.search.matches.clear();
+        if let Some(visible) = self.search.visible.clone() {
+            self.rescan_matches(visible);
+        }
+    }
+
+    /// finds the matches on the lines of `changed` that are visible again.
+    pub(crate) fn rescan_matches(&mut self, changed: Range<usize>) {
+        let (regex, visible) = match (&self.search.regex, &self.search.visible) {
+            (Some(regex), Some(visible)) => (regex, visible),
+            _ => return,
+        };
+        let text = &self.history[self.current].text;
+        let lines = |r: &Range<usize>| {
+            let line = |idx: usize| text.line_at(idx.min(text.len()));
+            text.line_start(line(r.start))..text.line_end(line(r.end))
+        };
+        let (visible, changed) = (lines(visible), lines(&changed));
+        let lo = changed.start.max(visible.start);
+        let hi = changed.end.min(visible.end);
+
+        let matches = &mut self.search.matches;
+        matches.retain(|m| m.start >= visible.start && m.end <= visible.end
+                       && ((m.end <= lo && m.start < lo) || m.start > hi));
+        if lo > hi {
+            return;
+        }
+        let mut found = vec![];
+        each_match(text, regex, lo..hi, |m| found.push(m));
+        let at = matches.partition_point(|m| m.start < lo);
+        matches.splice(at..at, found);
+    }
+
+    /// the first match after `from` in `dir`, going on from the other end
+    /// of the text if searches wrap.
+    fn find_match(&self, from: usize, dir: Direction) -> Option<SearchMatch> {
+        let regex = self.search.regex.as_ref()?;
+        let (mut total, mut found, mut wrapped) = (0, None, None);
+        each_match(self.text(), regex, 0..self.text().len(), |m| {
+            total += 1;
+            if total == 1 || dir == Direction::Backward {
+                wrapped = Some((m.clone(), total));
+            }
+            match dir {
+                Direction::Forward if found.is_none() && m.start > from => {
+                    found = Some((m, total));
+                },
+                Direction::Backward if m.start < from => found = Some((m, total)),
+                _ => (),
+            }
+        });
+        let (range, index) = found.or(wrapped.filter(|_| self.search.wrap))?;
+        Some(SearchMatch { range, index, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::boundary::Direction;
+    use crate::rope::Rope;
+    use crate::search::{CaseMode, SearchMatch, compile, each_match};
+
+    fn found(start: usize, end: usize, index: usize, total: usize) -> Option<SearchMatch> {
+        Some(SearchMatch { range: start..end, index, total })
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let mut b = Buffer::from(Rope::from("foo bar\nfoo baz\nbaz"));
+        b.set_search_region(0..19);
+        b.set_cursor(1);
+        b.start_search(Direction::Forward);
+        assert!(b.is_searching());
+        assert_eq!(b.set_search_pattern("b").unwrap(), found(4, 5, 1, 3));
+        assert_eq!(b.cursor(), 4);
+        assert_eq!(b.set_search_pattern("baz").unwrap(), found(12, 15, 1, 2));
+        assert_eq!(b.search_matches(), &[12..15, 16..19][..]);
+        assert_eq!(b.cursor(), 12);
+        // a partly typed pattern matches nothing
+        assert!(b.set_search_pattern("baz(").is_err());
+        assert!(b.search_matches().is_empty());
+        assert_eq!(b.cursor(), 1);
+        b.set_search_pattern("baz").unwrap();
+        b.finish_search();
+        assert!(!b.is_searching());
+        assert_eq!(b.cursor(), 12);
+        assert_eq!(b.jump_list(), (&[1][..], 1));
+
+        b.start_search(Direction::Backward);
+        b.set_search_pattern("foo").unwrap();
+        assert_eq!(b.cursor(), 8);
+        b.cancel_search();
+        assert_eq!(b.cursor(), 12);
+        assert_eq!(b.search_pattern(), "baz");
+    }
+
+    #[test]
+    fn test_search_next_prev() {
+        let mut b = Buffer::from(Rope::from("ab\nab\nab\n"));
+        b.set_search_pattern("^a").unwrap();
+        assert_eq!(b.search_next(), found(3, 4, 2, 3));
+        assert_eq!(b.search_next(), found(6, 7, 3, 3));
+        assert_eq!(b.search_next(), found(0, 1, 1, 3));
+        assert_eq!(b.search_prev(), found(6, 7, 3, 3));
+        assert_eq!(b.cursor(), 6);
+
+        b.set_search_wrap(false);
+        assert_eq!(b.search_next(), None);
+        assert_eq!(b.cursor(), 6);
+
+        b.start_search(Direction::Backward);
+        b.set_search_pattern("b").unwrap();
+        b.finish_search();
+        assert_eq!(b.search_next(), found(1, 2, 1, 3));
+        assert_eq!(b.search_next(), None);
+        assert_eq!(b.search_prev(), found(4, 5, 2, 3));
+
+        // a pattern with no matches goes nowhere, wrapping or not
+        b.set_search_pattern("c").unwrap();
+        b.set_search_wrap(true);
+        assert_eq!(b.search_next(), None);
+        assert_eq!(b.search_prev(), None);
+        assert_eq!(b.cursor(), 4);
+    }
+
+    #[test]
+    fn test_each_match() {
+        let text = Rope::from(&*"ab\n".repeat(50_000));
+        let count = |pattern: &str| {
+            let regex = compile(pattern, CaseMode::Sensitive).unwrap().unwrap();
+            let mut found = vec![];
+            each_match(&text, &regex, 0..text.len(), |m| found.push(m));
+            assert!(found.windows(2).all(|w| w[0].end <= w[1].start));
+            found.len()
+        };
+        assert_eq!(count("^a"), 50_000);
+        assert_eq!(count("b$"), 50_000);
+        assert_eq!(count("^"), 50_001);
+    }
+
+    #[test]
+    fn test_match_across_pieces() {
+        let mut b = Buffer::from(Rope::from(&*format!("{}\nfoo\nbar\n", "x".repeat(65532))));
+        let patterns = [(r"foo\nbar", 65533..65540), (r"foo\sbar", 65533..65540), ("o[^a]b", 65535..65538)];
+        for (pattern, range) in patterns.iter().cloned() {
+            b.set_search_pattern(pattern).unwrap();
+            b.set_cursor(0);
+            assert_eq!(b.search_next().map(|m| m.range), Some(range));
+        }
+        // a pattern that can't match a newline is still found in pieces
+        b.set_search_pattern("bar").unwrap();
+        b.set_cursor(0);
+        assert_eq!(b.search_next(), found(65537, 65540, 1, 1));
+    }
+
+    #[test]
+    fn test_smartcase() {
+        let mut b = Buffer::from(Rope::from("Foo foo FOO"));
+        b.set_search_region(0..11);
+        b.set_search_pattern("foo").unwrap();
+        assert_eq!(b.search_matches().len(), 3);
+        b.set_search_pattern("Foo").unwrap();
+        assert_eq!(b.search_matches().first(), Some(&(0..3)));
+        assert_eq!(b.search_matches().len(), 1);
+        // escapes are not uppercase letters
+        b.set_search_pattern(r"\Sfoo").unwrap();
+        assert_eq!(b.search_matches().len(), 0);
+        b.set_search_pattern(r"\bfoo").unwrap();
+        assert_eq!(b.search_matches().len(), 3);
+        b.set_search_case(CaseMode::Sensitive);
+        assert_eq!(b.search_matches().first(), Some(&(4..7)));
+        assert_eq!(b.search_matches().len(), 1);
+        b.set_search_case(CaseMode::Insensitive);
+        b.set_search_pattern("FOO").unwrap();
+        assert_eq!(b.search_matches().len(), 3);
+    }
+
+    #[test]
+    fn test_search_region() {
+        let mut b = Buffer::from(Rope::from("x\nx\nx\nx\n"));
+        b.set_search_pattern("x").unwrap();
+        assert!(b.search_matches().is_empty());
+        b.set_search_region(3..5);
+        assert_eq!(b.search_matches(), &[2..3, 4..5][..]);
+        // the region and its matches move with the text
+        b.set_cursor(0);
+        b.insert_at_cursor("y\n");
+        assert_eq!(b.search_matches(), &[4..5, 6..7][..]);
+        b.undo();
+        assert_eq!(b.search_matches(), &[2..3, 4..5][..]);
+
+        // only the lines an edit touches are searched again
+        b.set_search_region(0..8);
+        b.set_cursor(3);
+        b.insert_at_cursor("x");
+        assert_eq!(b.search_matches(), &[0..1, 2..3, 3..4, 5..6, 7..8][..]);
+        b.delete_range(2..4).unwrap();
+        assert_eq!(b.search_matches(), &[0..1, 3..4, 5..6][..]);
+        b.undo();
+        assert_eq!(b.search_matches(), &[0..1, 2..3, 3..4, 5..6, 7..8][..]);
+
+        b.begin_group();
+        b.delete_range(7..8).unwrap();
+        b.delete_range(0..1).unwrap();
+        b.end_group();
+        assert_eq!(b.search_matches(), &[1..2, 2..3, 4..5][..]);
+        b.undo();
+        assert_eq!(b.search_matches(), &[0..1, 2..3, 3..4, 5..6, 7..8][..]);
+    }
+}
@@ -0,0 +1,504 @@
+//! multiple cursors and selections.
+//!
+//! a selection is the text between its anchor and its head, which is where
+//! its cursor is. the primary selection lives in the buffer's cursor and
+//! `anchor`, and the others are kept beside it. motions and edits are run
+//! once for every selection, with each one made the cursor in turn, and the
+//! edits made for all of them are one undo step.
+//!
+//! selections are kept sorted, and ones that overlap are merged.
+use std::mem;
+use std::ops::Range;
+use regex::Regex;
+use crate::Buffer;
+use crate::boundary::WordKind;
+use crate::search::each_match;
+
+/// the text between `anchor` and `head`. the head is where the cursor is,
+/// and can be before the anchor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+    /// the display column vertical motions try to keep the head in
+    pub(crate) goal_col: Option<usize>,
+}
+
+impl Selection {
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Selection { anchor, head, goal_col: None }
+    }
+
+    /// an empty selection, which is just a cursor
+    pub fn cursor(idx: usize) -> Self {
+        Selection::new(idx, idx)
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// true if the head is before the anchor
+    pub fn is_backward(&self) -> bool {
+        self.head < self.anchor
+    }
+}
+
+/// where `pos` ends up after the bytes `start..end` are replaced by `len`
+/// new ones. positions inside of the replaced bytes move after the new ones.
+pub(crate) fn shift(pos: usize, start: usize, end: usize, len: usize) -> usize {
+    if pos <= start {
+        pos
+    } else if pos >= end {
+        pos - (end - start) + len
+    } else {
+        start + len
+    }
+}
+
+/// true if `a` and `b` share some text or start at the same place, so they
+/// would be merged
+fn overlaps(a: &Selection, b: &Selection) -> bool {
+    (a.start() < b.end() && b.start() < a.end()) || a.start() == b.start()
+}
+
+/// sorts `sels` and merges the ones that overlap. returns where the
+/// selection that was at `primary` ended up.
+fn normalize(sels: &mut Vec<Selection>, primary: usize) -> usize {
+    let primary_sel = sels[primary];
+    let mut order: Vec<_> = (0..sels.len()).collect();
+    order.sort_by_key(|&i| (sels[i].start(), sels[i].end()));
+
+    let mut merged: Vec<Selection> = vec![];
+    let mut new_primary = 0;
+    for i in order {
+        let sel = sels[i];
+        match merged.last_mut() {
+            Some(last) if overlaps(last, &sel) => {
+                let (start, end) = (last.start(), last.end().max(sel.end()));
+                *last = if last.is_backward() {
+                    Selection::new(end, start)
+                } else {
+                    Selection::new(start, end)
+                };
+                if i == primary {
+                    new_primary = merged.len() - 1;
+                }
+            },
+            _ => {
+                if i == primary {
+                    new_primary = merged.len();
+                }
+                merged.push(sel);
+            },
+        }
+    }
+    // a merged primary keeps where its cursor was
+    if merged[new_primary].range() == primary_sel.range() {
+        merged[new_primary] = primary_sel;
+    }
+    *sels = merged;
+    new_primary
+}
+
+impl Buffer {
+    /// the primary selection, whose head is the cursor
+    pub fn selection(&self) -> Selection {
+        Selection {
+            anchor: self.anchor,
+            head: self.cursor(),
+            goal_col: self.goal_col,
+        }
+    }
+
+    /// every selection in order, and the index of the primary one
+    pub fn selections(&self) -> (Vec<Selection>, usize) {
+        let sel = self.selection();
+        let mut sels = self.others.clone();
+        let primary = sels.iter()
+            .position(|s| (s.start(), s.end()) > (sel.start(), sel.end()))
+            .unwrap_or(sels.len());
+        sels.insert(primary, sel);
+        (sels, primary)
+    }
+
+    /// the number of selections, which is 1 when there is only the cursor
+    pub fn selection_count(&self) -> usize {
+        self.others.len() + 1
+    }
+
+    /// replaces every selection with `sels`, sorted and merged. the one at
+    /// `primary` becomes the primary selection. does nothing if there are
+    /// none.
+    pub fn set_selections(&mut self, mut sels: Vec<Selection>, primary: usize) {
+        if sels.is_empty() {
+            return;
+        }
+        let len = self.text().len();
+        for sel in &mut sels {
+            sel.anchor = sel.anchor.min(len);
+            sel.head = sel.head.min(len);
+        }
+        let primary = primary.min(sels.len() - 1);
+        let primary = normalize(&mut sels, primary);
+        self.break_typing();
+        self.load_selection(sels.remove(primary));
+        self.others = sels;
+    }
+
+    /// makes the primary selection run from `anchor` to `head`, where the
+    /// cursor is left.
+    pub fn select(&mut self, anchor: usize, head: usize) {
+        let (mut sels, primary) = self.selections();
+        sels[primary] = Selection::new(anchor, head);
+        self.set_selections(sels, primary);
+    }
+
+    /// drops every selection but the primary one, and empties that, leaving
+    /// just the cursor.
+    pub fn collapse_selections(&mut self) {
+        self.others.clear();
+        self.anchor = self.cursor();
+    }
+
+    /// sets where the primary selection starts from, leaving the cursor at
+    /// its head.
+    pub fn set_anchor(&mut self, idx: usize) {
+        self.anchor = idx.min(self.text().len());
+    }
+
+    /// true if motions move the heads of the selections and leave their
+    /// anchors, growing the selections instead of moving them.
+    pub fn is_extending(&self) -> bool {
+        self.extending
+    }
+
+    pub fn set_extending(&mut self, extending: bool) {
+        self.extending = extending;
+    }
+
+    /// adds a cursor on the line above the first selection, in the display
+    /// column of the primary cursor. it becomes the primary cursor. returns
+    /// false if the first selection is on the first line.
+    pub fn add_cursor_above(&mut self) -> bool {
+        let (sels, _) = self.selections();
+        let line = self.text().line_at(sels[0].head);
+        match line.checked_sub(1) {
+            Some(line) => self.add_cursor_on_line(line),
+            None => false,
+        }
+    }
+
+    /// adds a cursor on the line below the last selection, like
+    /// add_cursor_above. returns false if that is on the last line.
+    pub fn add_cursor_below(&mut self) -> bool {
+        let (sels, _) = self.selections();
+        let line = self.text().line_at(sels[sels.len() - 1].head);
+        if line >= self.text().lenlines() {
+            return false;
+        }
+        self.add_cursor_on_line(line + 1)
+    }
+
+    fn add_cursor_on_line(&mut self, line: usize) -> bool {
+        let goal = self.goal_col.unwrap_or_else(|| self.column());
+        let idx = self.text().byte_at_column(line, goal, self.tab_width);
+        let mut sel = Selection::cursor(idx);
+        sel.goal_col = Some(goal);
+        self.add_selection(sel)
+    }
+
+    /// selects the next place the text of the primary selection appears
+    /// after it, wrapping around the end of the text, and makes that the
+    /// primary selection, like sublime's `ctrl+d`. an empty primary
+    /// selection selects the word under the cursor instead. returns false if
+    /// nothing was selected.
+    pub fn add_selection_at_next_match(&mut self) -> bool {
+        let sel = self.selection();
+        if sel.is_empty() {
+            let word = self.text().word_at(sel.head, WordKind::Word);
+            let blank = self.text().chars_from(word.start).next()
+                .is_none_or(|(_, ch)| ch.is_whitespace());
+            if word.is_empty() || blank {
+                return false;
+            }
+            self.select(word.start, word.end);
+            return true;
+        }
+
+        let needle: String = self.text().char_slice(sel.range()).str_iter().collect();
+        let regex = match Regex::new(&regex::escape(&needle)) {
+            Ok(regex) => regex,
+            Err(_) => return false,
+        };
+        // the first match after the selection, or else from the start
+        let mut found = None;
+        for range in [sel.end()..self.text().len(), 0..sel.start()].iter().cloned() {
+            each_match(self.text(), &regex, range, |m| {
+                found.get_or_insert(m);
+            });
+            if found.is_some() {
+                break;
+            }
+        }
+        match found {
+            Some(m) => self.add_selection(Selection::new(m.start, m.end)),
+            None => false,
+        }
+    }
+
+    /// splits every selection into one selection per line it covers, with
+    /// the cursor at the end of each, so the same edit can be made on all of
+    /// the lines at once. empty selections are left alone.
+    pub fn split_selection_lines(&mut self) {
+        let (sels, primary) = self.selections();
+        let mut split = vec![];
+        let mut new_primary = 0;
+        for (i, sel) in sels.into_iter().enumerate() {
+            let text = self.text();
+            let (first, last) = (text.line_at(sel.start()), text.line_at(sel.end()));
+            for line in first..=last {
+                let start = text.line_start(line).max(sel.start());
+                let end = text.line_end(line).min(sel.end());
+                // the end of a selection that ends at the start of a line
+                // doesn't cover that line
+                if line > first && start == end && end == sel.end() {
+                    continue;
+                }
+                if i == primary {
+                    new_primary = split.len();
+                }
+                split.push(Selection::new(start, end));
+            }
+        }
+        self.set_selections(split, new_primary);
+    }
+
+    /// adds `sel` as the new primary selection. returns false if it
+    /// overlaps one that is already there.
+    fn add_selection(&mut self, sel: Selection) -> bool {
+        let (mut sels, _) = self.selections();
+        if sels.iter().any(|s| overlaps(s, &sel)) {
+            return false;
+        }
+        sels.push(sel);
+        let primary = sels.len() - 1;
+        self.set_selections(sels, primary);
+        true
+    }
+
+    /// runs `f` once for every selection, with that selection as the cursor
+    /// and anchor, in order from the start of the text. the edits it makes
+    /// are one undo step.
+    pub(crate) fn each_selection(&mut self, f: impl FnMut(&mut Buffer)) {
+        self.each_selection_typing("", f);
+    }
+
+    /// each_selection for typing `typed` at every selection. the edits join
+    /// the run of typing before them, as they would with a single cursor.
+    pub(crate) fn each_selection_typing(&mut self, typed: &str, mut f: impl FnMut(&mut Buffer)) {
+        if self.others.is_empty() {
+            f(self);
+            return;
+        }
+
+        if self.selections().0.iter().any(|s| !s.is_empty()) {
+            self.break_typing();
+        }
+        self.begin_typing(typed);
+        let (mut sels, primary) = self.selections();
+        for i in 0..sels.len() {
+            // the edits made for this selection move the others
+            let sel = sels.remove(i);
+            self.others = mem::take(&mut sels);
+            self.load_selection(sel);
+            f(self);
+            sels = mem::take(&mut self.others);
+            sels.insert(i, self.selection());
+        }
+        self.end_typing(typed);
+
+        let primary = normalize(&mut sels, primary);
+        self.load_selection(sels.remove(primary));
+        self.others = sels;
+    }
+
+    /// moves every selection other than the cursor's through the
+    /// replacement of bytes `start..end` with `len` new ones.
+    pub(crate) fn shift_selections(&mut self, start: usize, end: usize, len: usize) {
+        for sel in &mut self.others {
+            sel.anchor = shift(sel.anchor, start, end, len);
+            sel.head = shift(sel.head, start, end, len);
+        }
+    }
+
+    fn load_selection(&mut self, sel: Selection) {
+        self.place(sel.head);
+        self.anchor = sel.anchor;
+        self.goal_col = sel.goal_col;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::rope::Rope;
+    use crate::selection::{Selection, normalize, shift};
+
+    fn ranges(b: &Buffer) -> Vec<(usize, usize)> {
+        b.selections().0.iter().map(|s| (s.anchor, s.head)).collect()
+    }
+
+    #[test]
+    fn test_shift() {
+        assert_eq!(shift(2, 3, 5, 1), 2);
+        assert_eq!(shift(3, 3, 3, 2), 3);
+        assert_eq!(shift(4, 3, 5, 1), 4);
+        assert_eq!(shift(7, 3, 5, 1), 6);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut sels = vec![
+            Selection::new(8, 6),
+            Selection::cursor(0),
+            Selection::new(5, 7),
+            Selection::cursor(0),
+            Selection::new(10, 12),
+        ];
+        assert_eq!(normalize(&mut sels, 2), 1);
+        assert_eq!(sels, vec![Selection::cursor(0), Selection::new(5, 8), Selection::new(10, 12)]);
+        let mut sels = vec![Selection::new(3, 1)];
+        assert_eq!(normalize(&mut sels, 0), 0);
+        assert_eq!(sels, vec![Selection::new(3, 1)]);
+    }
+
+    #[test]
+    fn test_add_cursor_above_below() {
+        let mut b = Buffer::from(Rope::from("abcd\nx\nabcd"));
+        assert!(!b.add_cursor_above());
+        b.set_cursor(3);
+        assert!(b.add_cursor_below());
+        assert!(b.add_cursor_below());
+        assert!(!b.add_cursor_below());
+        assert_eq!(ranges(&b), vec![(3, 3), (6, 6), (10, 10)]);
+        assert_eq!(b.cursor(), 10);
+
+        // one undo step for every edit
+        b.insert_at_cursor("!");
+        assert_eq!(*b.text(), *"abc!d\nx!\nabc!d");
+        assert_eq!(ranges(&b), vec![(4, 4), (8, 8), (13, 13)]);
+        b.backspace();
+        b.undo();
+        assert_eq!(*b.text(), *"abc!d\nx!\nabc!d");
+        b.undo();
+        assert_eq!(*b.text(), *"abcd\nx\nabcd");
+        assert_eq!(b.selection_count(), 1);
+    }
+
+    #[test]
+    fn test_motions_merge() {
+        let mut b = Buffer::from(Rope::from("ab\ncd\n"));
+        b.add_cursor_below();
+        b.move_right();
+        assert_eq!(ranges(&b), vec![(1, 1), (4, 4)]);
+        b.move_up();
+        assert_eq!(ranges(&b), vec![(1, 1)]);
+
+        b.set_selections(vec![Selection::cursor(3), Selection::cursor(0)], 0);
+        assert_eq!(b.cursor(), 3);
+        b.set_extending(true);
+        b.move_right();
+        b.move_right();
+        assert_eq!(ranges(&b), vec![(0, 2), (3, 5)]);
+        b.set_extending(false);
+        b.move_line_start();
+        assert_eq!(ranges(&b), vec![(0, 0), (3, 3)]);
+
+        // edits elsewhere move the other selections
+        b.set_selections(vec![Selection::cursor(0), Selection::cursor(3)], 0);
+        b.replace_range(1..2, "xyz").unwrap();
+        assert_eq!(ranges(&b), vec![(1, 1), (5, 5)]);
+        b.collapse_selections();
+        assert_eq!(ranges(&b), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn test_next_match() {
+        let mut b = Buffer::from(Rope::from("foo bar foo baz foo"));
+        b.set_cursor(9);
+        assert!(b.add_selection_at_next_match());
+        assert_eq!(ranges(&b), vec![(8, 11)]);
+        assert!(b.add_selection_at_next_match());
+        assert!(b.add_selection_at_next_match());
+        assert_eq!(ranges(&b), vec![(0, 3), (8, 11), (16, 19)]);
+        assert_eq!(b.cursor(), 3);
+        assert!(!b.add_selection_at_next_match());
+
+        b.insert_at_cursor("d");
+        assert_eq!(*b.text(), *"d bar d baz d");
+        let mut b = Buffer::from(Rope::from("  "));
+        assert!(!b.add_selection_at_next_match());
+
+        // the selection is found as it is, not as a pattern
+        let mut b = Buffer::from(Rope::from("a.b\naxb a.b\n"));
+        b.select(0, 4);
+        assert!(b.add_selection_at_next_match());
+        assert_eq!(ranges(&b), vec![(0, 4), (8, 12)]);
+    }
+
+    #[test]
+    fn test_typing() {
+        let mut b = Buffer::from(Rope::from("ab\ncd\nef\n"));
+        b.add_cursor_below();
+        b.add_cursor_below();
+        for ch in ["x", "y", "z"].iter() {
+            b.insert_at_cursor(ch);
+        }
+        assert_eq!(*b.text(), *"xyzab\nxyzcd\nxyzef\n");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"ab\ncd\nef\n");
+        assert!(b.redo());
+
+        // typing over selections replaces them, as a new undo step
+        b.set_selections(vec![Selection::new(3, 5), Selection::new(15, 17)], 1);
+        b.insert_at_cursor("1");
+        b.insert_at_cursor("2");
+        assert_eq!(*b.text(), *"xyz12\nxyzcd\nxyz12\n");
+        assert_eq!(ranges(&b), vec![(5, 5), (17, 17)]);
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"xyzab\nxyzcd\nxyzef\n");
+
+        let mut b = Buffer::from(Rope::from("a b"));
+        b.select(0, 1);
+        b.insert_newline();
+        assert_eq!(*b.text(), *"\n b");
+    }
+
+    #[test]
+    fn test_split_selection_lines() {
+        let mut b = Buffer::from(Rope::from("one\ntwo\nthree\n"));
+        b.select(1, 10);
+        b.split_selection_lines();
+        assert_eq!(ranges(&b), vec![(1, 3), (4, 7), (8, 10)]);
+        assert_eq!(b.cursor(), 10);
+        b.backspace();
+        assert_eq!(*b.text(), *"o\n\nree\n");
+
+        b.select(0, 6);
+        b.split_selection_lines();
+        assert_eq!(ranges(&b), vec![(0, 1), (2, 2), (3, 6)]);
+    }
+}
@@ -0,0 +1,485 @@
+//! substituting the matches of a regex, like vim's `:s`.
+//!
+//! every match is found in the text as it was before the first one is
+//! replaced, so a replacement is never matched again. each match can be
+//! confirmed on its own, and all of the replacements are undone together.
+//! any other edit or undo made while a match waits for an answer ends the
+//! substitution.
+use std::mem;
+use std::ops::Range;
+use regex::{Captures, Regex};
+use crate::Buffer;
+use crate::search::compile;
+use crate::visual::VisualMode;
+
+/// the text a substitution works on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubstituteRange {
+    /// lines `first` to `last`, including both
+    Lines(usize, usize),
+    /// the visual selection, or the primary selection outside visual mode.
+    /// a block covers its whole lines.
+    Selection,
+    Whole,
+}
+
+/// an answer to whether a match should be replaced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confirm {
+    Yes,
+    No,
+    /// replace this match and all of the ones after it
+    All,
+    /// stop, keeping the replacements made so far
+    Quit,
+}
+
+/// where a substitution is up to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubstituteStep {
+    /// waiting for an answer for the match on these bytes. the cursor is at
+    /// its start.
+    Confirm(Range<usize>),
+    /// finished, having replaced this many matches
+    Done(usize),
+}
+
+#[derive(Clone, Copy)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// a substitution waiting for answers
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    /// the matches, and what they are replaced with. they are offsets from
+    /// `start`, in the text as it was.
+    matches: Vec<(Range<usize>, String)>,
+    /// the match waiting for an answer
+    next: usize,
+    start: usize,
+    /// the bytes added by the replacements so far, less the ones removed
+    grown: isize,
+    count: usize,
+    /// where the last replacement was made
+    last: Option<usize>,
+    /// where the cursor was before
+    origin: usize,
+}
+
+impl Substitution {
+    /// where byte `i` of the searched text is in the buffer now
+    fn offset(&self, i: usize) -> usize {
+        ((self.start + i) as isize + self.grown) as usize
+    }
+}
+
+fn push_case(out: &mut String, s: &str, one: &mut Option<Case>, all: Option<Case>) {
+    for ch in s.chars() {
+        match one.take().or(all) {
+            Some(Case::Upper) => out.extend(ch.to_uppercase()),
+            Some(Case::Lower) => out.extend(ch.to_lowercase()),
+            None => out.push(ch),
+        }
+    }
+}
+
+/// what a match is replaced with. in `replacement`, `&` and `\0` are the
+/// whole match and `\1` to `\9` its groups. `\u` and `\l` change the case
+/// of the next letter, `\U` and `\L` of the ones up to `\E`, and `\r` and
+/// `\n` are newlines. other escaped characters are kept as they are.
+fn expand(replacement: &str, caps: &Captures) -> String {
+    let mut out = String::new();
+    let (mut one, mut all) = (None, None);
+    let mut buf = [0; 4];
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        let escaped = if ch == '\\' { chars.next() } else { None };
+        let s = match (ch, escaped) {
+            ('&', _) => caps.get(0).map_or("", |m| m.as_str()),
+            (_, Some(d)) if d.is_ascii_digit() => {
+                caps.get(d as usize - '0' as usize).map_or("", |m| m.as_str())
+            },
+            (_, Some('u')) => { one = Some(Case::Upper); continue },
+            (_, Some('l')) => { one = Some(Case::Lower); continue },
+            (_, Some('U')) => { all = Some(Case::Upper); continue },
+            (_, Some('L')) => { all = Some(Case::Lower); continue },
+            (_, Some('E')) | (_, Some('e')) => { all = None; continue },
+            (_, Some('r')) | (_, Some('n')) => "\n",
+            (_, Some('t')) => "\t",
+            (_, Some(c)) => c.encode_utf8(&mut buf),
+            (c, None) => c.encode_utf8(&mut buf),
+        };
+        push_case(&mut out, s, &mut one, all);
+    }
+    out
+}
+
+fn next_char(text: &str, i: usize) -> usize {
+    i + text[i..].chars().next().map_or(1, char::len_utf8)
+}
+
+/// the matches of `regex` in `lo..hi` of `text`, only the first on each
+/// line unless `global`. an empty match right after another one is
+/// skipped, as is one at the start of the line after `hi`.
+fn find_matches(regex: &Regex, text: &str, lo: usize, hi: usize, global: bool, replacement: &str)
+                -> Vec<(Range<usize>, String)> {
+    let mut found = vec![];
+    let mut at = lo;
+    let mut last_end = None;
+    while at <= hi {
+        let caps = match regex.captures_at(text, at) {
+            Some(caps) => caps,
+            None => break,
+        };
+        let m = caps.get(0).unwrap();
+        if m.end() > hi || (m.start() == hi && hi > lo && text[..hi].ends_with('\n')) {
+            break;
+        }
+        if m.is_empty() && last_end == Some(m.start()) {
+            at = next_char(text, m.start());
+            continue;
+        }
+        found.push((m.range(), expand(replacement, &caps)));
+        if global {
+            last_end = Some(m.end());
+            at = if m.is_empty() { next_char(text, m.start()) } else { m.end() };
+        } else {
+            at = text[m.start()..].find('\n')
+                .map_or(text.len() + 1, |i| m.start() + i + 1)
+                .max(m.end());
+        }
+    }
+    found
+}
+
+impl Buffer {
+    /// replaces every match of `pattern` in `range` with `replacement`, as
+    /// one undo step. returns how many were replaced. see
+    /// `start_substitute`.
+    pub fn substitute(&mut self, range: SubstituteRange, pattern: &str,
+                      replacement: &str, global: bool) -> Result<usize, regex::Error> {
+        self.start_substitute(range, pattern, replacement, global)?;
+        match self.confirm_substitute(Confirm::All) {
+            SubstituteStep::Done(count) => Ok(count),
+            SubstituteStep::Confirm(_) => unreachable!(),
+        }
+    }
+
+    /// starts replacing matches of `pattern` in `range` with `replacement`,
+    /// asking to confirm each one, like vim's `:s` with the `c` flag. only
+    /// the first match on each line is replaced unless `global`, and lines
+    /// past the end of the text have nothing to replace. an empty
+    /// pattern is the last search pattern, and the pattern becomes the last
+    /// search pattern. matches use the search case mode. see `expand` for
+    /// what `replacement` can refer to.
+    pub fn start_substitute(&mut self, range: SubstituteRange, pattern: &str,
+                            replacement: &str, global: bool) -> Result<SubstituteStep, regex::Error> {
+        if self.substitution.is_some() {
+            self.confirm_substitute(Confirm::Quit);
+        }
+        let pattern = match pattern {
+            "" => self.search_pattern().to_string(),
+            _ => pattern.to_string(),
+        };
+        let regex = match compile(&pattern, self.search_case())? {
+            Some(regex) => regex,
+            None => return Ok(SubstituteStep::Done(0)),
+        };
+        if !self.is_searching() {
+            let _ = self.set_search_pattern(&pattern);
+        }
+
+        let bytes = match self.substitute_bytes(range) {
+            Some(bytes) => bytes,
+            None => return Ok(SubstituteStep::Done(0)),
+        };
+        let text = self.text();
+        let start = text.line_start(text.line_at(bytes.start));
+        let end = text.line_end(text.line_at(bytes.end));
+        let s: String = text.char_slice(start..end).str_iter().collect();
+        let matches = find_matches(&regex, &s, bytes.start - start, bytes.end - start,
+                                   global, replacement);
+        if self.visual_mode().is_some() {
+            self.exit_visual();
+        }
+
+        self.substitution = Some(Substitution {
+            matches,
+            next: 0,
+            start,
+            grown: 0,
+            count: 0,
+            last: None,
+            origin: self.cursor(),
+        });
+        self.begin_group();
+        Ok(self.substitute_step())
+    }
+
+    /// answers whether to replace the match the substitution is waiting
+    /// on. once it is done, the cursor is left at the start of the last
+    /// replacement, or where it was if there was none.
+    pub fn confirm_substitute(&mut self, answer: Confirm) -> SubstituteStep {
+        let sub = match &mut self.substitution {
+            Some(sub) => sub,
+            None => return SubstituteStep::Done(0),
+        };
+        match answer {
+            Confirm::Yes => self.replace_matches(1),
+            Confirm::No => sub.next += 1,
+            Confirm::All => self.replace_matches(usize::MAX),
+            Confirm::Quit => sub.next = sub.matches.len(),
+        }
+        self.substitute_step()
+    }
+
+    /// whether a substitution is waiting for an answer
+    pub fn is_substituting(&self) -> bool {
+        self.substitution.is_some()
+    }
+
+    /// stops the substitution without asking about the rest of its matches,
+    /// keeping the replacements made so far. called before any edit or undo
+    /// the substitution did not make itself, which would leave the matches
+    /// it found out of date, and should not be undone with it.
+    pub(crate) fn interrupt_substitute(&mut self) {
+        if self.substitution.take().is_some() {
+            self.end_group();
+        }
+    }
+
+    /// the bytes `range` covers, or None if its lines start past the end of
+    /// the text
+    fn substitute_bytes(&self, range: SubstituteRange) -> Option<Range<usize>> {
+        let text = self.text();
+        let bytes = match range {
+            SubstituteRange::Lines(first, _) if first > text.lenlines() => return None,
+            SubstituteRange::Lines(first, last) => {
+                let last = last.min(text.lenlines());
+                text.line_start(first.min(last))..text.line_start(last + 1)
+            },
+            SubstituteRange::Selection => {
+                let (first, last) = self.visual_lines();
+                match self.visual_mode() {
+                    Some(VisualMode::Block) => self.visual_range(VisualMode::Line, first, last),
+                    Some(mode) => self.visual_range(mode, first, last),
+                    None => self.selection().range(),
+                }
+            },
+            SubstituteRange::Whole => 0..text.len(),
+        };
+        Some(bytes)
+    }
+
+    /// moves the cursor to the match waiting for an answer, or finishes the
+    /// substitution if there is none.
+    fn substitute_step(&mut self) -> SubstituteStep {
+        let sub = match &self.substitution {
+            Some(sub) => sub,
+            None => return SubstituteStep::Done(0),
+        };
+        if let Some((r, _)) = sub.matches.get(sub.next) {
+            let start = sub.offset(r.start);
+            let range = start..start + r.len();
+            self.set_cursor(start);
+            return SubstituteStep::Confirm(range);
+        }
+        let count = sub.count;
+        let cursor = sub.last.unwrap_or(sub.origin);
+        self.substitution = None;
+        self.end_group();
+        self.set_cursor(cursor);
+        SubstituteStep::Done(count)
+    }
+
+    /// replaces the next `n` matches, or all that are left if there are
+    /// fewer, as one edit.
+    fn replace_matches(&mut self, n: usize) {
+        let sub = match &mut self.substitution {
+            Some(sub) => sub,
+            None => return,
+        };
+        let end = sub.matches.len().min(sub.next.saturating_add(n));
+        let mut replacements = vec![];
+        for i in sub.next..end {
+            let start = sub.offset(sub.matches[i].0.start);
+            let (r, with) = &mut sub.matches[i];
+            replacements.push((start..start + r.len(), mem::take(with)));
+        }
+        let growth = |(r, with): &(Range<usize>, String)| with.len() as isize - r.len() as isize;
+        let grown: isize = replacements.iter().map(growth).sum();
+        // the last replacement starts after what the ones before it grew
+        let last = match replacements.last() {
+            Some(rep) => (rep.0.start as isize + grown - growth(rep)) as usize,
+            None => return,
+        };
+        sub.grown += grown;
+        sub.next = end;
+        sub.count += replacements.len();
+        sub.last = Some(last);
+        self.edit_ranges(&replacements, last);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use crate::Buffer;
+    use crate::marks::GlobalMarks;
+    use crate::rope::Rope;
+    use crate::visual::VisualMode;
+    use crate::substitute::{expand, Confirm, SubstituteRange, SubstituteStep};
+
+    fn expanded(pattern: &str, text: &str, replacement: &str) -> String {
+        let caps = Regex::new(pattern).unwrap().captures(text).unwrap();
+        expand(replacement, &caps)
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expanded(r"(\w+) (\w+)", "hello world", r"\2 \1"), "world hello");
+        assert_eq!(expanded(r"(\w+) (\w+)", "hello world", r"\U\2\E \u\1"), "WORLD Hello");
+        assert_eq!(expanded(r"(\w+) (\w+)", "HELLO world", r"\L\1 \u&"), "hello Hello world");
+        assert_eq!(expanded(r"(\w+) (\w+)", "hello world", r"\l\U\1"), "hELLO");
+        assert_eq!(expanded(r"b", "b", r"[\0\&\\\r\3]"), "[b&\\\n]");
+        assert_eq!(expanded(r"b", "b", r"\u"), "");
+    }
+
+    #[test]
+    fn test_substitute_lines() {
+        let mut b = Buffer::from(Rope::from("a a\na a\na a\n"));
+        assert_eq!(b.substitute(SubstituteRange::Lines(1, 1), "a", "bb", true), Ok(2));
+        assert_eq!(*b.text(), *"a a\nbb bb\na a\n");
+        assert_eq!(b.cursor(), 7);
+        assert_eq!(b.search_pattern(), "a");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"a a\na a\na a\n");
+
+        assert_eq!(b.substitute(SubstituteRange::Whole, "", "b", false), Ok(3));
+        assert_eq!(*b.text(), *"b a\nb a\nb a\n");
+        // `^` does not match the line after the range
+        assert_eq!(b.substitute(SubstituteRange::Lines(0, 1), "^", "> ", true), Ok(2));
+        assert_eq!(*b.text(), *"> b a\n> b a\nb a\n");
+        // lines past the end are not the last line
+        assert_eq!(b.substitute(SubstituteRange::Lines(5, 9), "$", "!", true), Ok(0));
+        assert_eq!(*b.text(), *"> b a\n> b a\nb a\n");
+        assert_eq!(b.substitute(SubstituteRange::Whole, "z", "y", true), Ok(0));
+        assert!(b.substitute(SubstituteRange::Whole, "(", "y", true).is_err());
+        assert_eq!(b.history.len(), 4);
+    }
+
+    #[test]
+    fn test_substitute_matches() {
+        let mut b = Buffer::from(Rope::from("xab"));
+        // empty matches right after another match are skipped
+        assert_eq!(b.substitute(SubstituteRange::Whole, "x*", "-", true), Ok(3));
+        assert_eq!(*b.text(), *"-a-b-");
+        // replacements are not matched again
+        assert_eq!(b.substitute(SubstituteRange::Whole, "-", "--", true), Ok(3));
+        assert_eq!(*b.text(), *"--a--b--");
+        // matches can join lines
+        let mut b = Buffer::from(Rope::from("one\ntwo\nthree\n"));
+        assert_eq!(b.substitute(SubstituteRange::Lines(0, 1), r"\n", ", ", true), Ok(2));
+        assert_eq!(*b.text(), *"one, two, three\n");
+    }
+
+    #[test]
+    fn test_substitute_selection() {
+        let mut b = Buffer::from(Rope::from("foo foo\nfoo foo\n"));
+        b.set_cursor(4);
+        b.start_visual(VisualMode::Char);
+        b.move_down();
+        assert_eq!(b.substitute(SubstituteRange::Selection, "foo", "bar", true), Ok(2));
+        assert_eq!(*b.text(), *"foo bar\nbar foo\n");
+        assert_eq!(b.visual_mode(), None);
+
+        b.start_visual(VisualMode::Line);
+        assert_eq!(b.substitute(SubstituteRange::Selection, "o", "0", true), Ok(2));
+        assert_eq!(*b.text(), *"foo bar\nbar f00\n");
+
+        b.select(0, 3);
+        assert_eq!(b.substitute(SubstituteRange::Selection, r"\w", "x", true), Ok(3));
+        assert_eq!(*b.text(), *"xxx bar\nbar f00\n");
+    }
+
+    #[test]
+    fn test_confirm_substitute() {
+        let mut b = Buffer::from(Rope::from("ab ab\nab ab\nab ab\n"));
+        b.set_cursor(3);
+        let step = b.start_substitute(SubstituteRange::Whole, "a(b)", r"\U\1\1", true).unwrap();
+        assert_eq!(step, SubstituteStep::Confirm(0..2));
+        assert!(b.is_substituting());
+        assert_eq!(b.confirm_substitute(Confirm::Yes), SubstituteStep::Confirm(3..5));
+        assert_eq!(b.cursor(), 3);
+        assert_eq!(b.confirm_substitute(Confirm::No), SubstituteStep::Confirm(6..8));
+        assert_eq!(b.confirm_substitute(Confirm::Yes), SubstituteStep::Confirm(9..11));
+        assert_eq!(b.confirm_substitute(Confirm::Quit), SubstituteStep::Done(2));
+        assert!(!b.is_substituting());
+        assert_eq!(*b.text(), *"BB ab\nBB ab\nab ab\n");
+        assert_eq!(b.cursor(), 6);
+
+        b.start_substitute(SubstituteRange::Lines(1, 2), "ab", "c", false).unwrap();
+        assert_eq!(b.confirm_substitute(Confirm::No), SubstituteStep::Confirm(12..14));
+        assert_eq!(b.confirm_substitute(Confirm::All), SubstituteStep::Done(1));
+        assert_eq!(*b.text(), *"BB ab\nBB ab\nc ab\n");
+
+        // a substitution is one undo step however it is answered
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"BB ab\nBB ab\nab ab\n");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"ab ab\nab ab\nab ab\n");
+
+        // nothing replaced leaves the cursor where it was
+        b.set_cursor(3);
+        let step = b.start_substitute(SubstituteRange::Whole, "ab", "", true).unwrap();
+        assert_eq!(step, SubstituteStep::Confirm(0..2));
+        assert_eq!(b.confirm_substitute(Confirm::No), SubstituteStep::Confirm(3..5));
+        assert_eq!(b.confirm_substitute(Confirm::Quit), SubstituteStep::Done(0));
+        assert_eq!(b.cursor(), 3);
+        assert_eq!(b.history.len(), 3);
+    }
+
+    #[test]
+    fn test_interrupted_substitute() {
+        let mut b = Buffer::from(Rope::from("ab ab\nab\n"));
+        b.start_substitute(SubstituteRange::Whole, "ab", "x", true).unwrap();
+        assert_eq!(b.confirm_substitute(Confirm::Yes), SubstituteStep::Confirm(2..4));
+
+        // an edit made while waiting ends the substitution, and is undone
+        // on its own
+        b.move_buffer_end();
+        b.insert_at_cursor("!");
+        assert!(!b.is_substituting());
+        assert_eq!(b.confirm_substitute(Confirm::Yes), SubstituteStep::Done(0));
+        assert_eq!(*b.text(), *"x ab\nab\n!");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"x ab\nab\n");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"ab ab\nab\n");
+
+        // and so does an undo
+        b.start_substitute(SubstituteRange::Whole, "ab", "x", true).unwrap();
+        b.confirm_substitute(Confirm::Yes);
+        assert!(b.undo());
+        assert!(!b.is_substituting());
+        assert_eq!(*b.text(), *"ab ab\nab\n");
+    }
+
+    #[test]
+    fn test_substitute_moves_marks() {
+        let mut globals = GlobalMarks::new();
+        let mut b = Buffer::from(Rope::from("ab ab ab"));
+        b.set_cursor(3);
+        b.set_mark('a', &mut globals);
+        assert_eq!(b.substitute(SubstituteRange::Whole, "b", "xyz", true), Ok(3));
+        assert_eq!(*b.text(), *"axyz axyz axyz");
+        assert_eq!(b.cursor(), 11);
+        assert_eq!(b.mark('a', &globals), Some(5));
+        assert!(b.undo());
+        assert_eq!(b.mark('a', &globals), Some(3));
+        assert!(b.redo());
+        assert_eq!(b.mark('a', &globals), Some(5));
+    }
+}
@@ -0,0 +1,381 @@
+//! journals the changes to a buffer in a swap file next to its file, so
+//! they can be recovered if the editor dies before saving them.
+//!
+//! a swap file starts with the hash of the file contents its journal
+//! applies to and the id of the process writing it. every change to the
+//! text is appended as the replacement of a range, and recovering replays
+//! them over the file. the swap file is removed when the buffer is dropped,
+//! unless that happens because of a panic.
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::Buffer;
+use crate::hash::hash_rope;
+use crate::rope::Rope;
+use crate::undofile::Delta;
+
+const MAGIC: &[u8; 8] = b"piswap\x00\x01";
+
+/// the longest that journaled changes go without being synced to disk
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the swap file for the file at `path`, which is `.name.swp` in the same
+/// directory, like vim's.
+pub fn swap_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".swp");
+    path.with_file_name(name)
+}
+
+/// the swap file a buffer is journaling to
+pub(crate) struct Swap {
+    file: File,
+    path: PathBuf,
+    synced: Instant,
+}
+
+/// a change to the text: `removed` bytes at `start` replaced by `inserted`
+struct Record {
+    start: usize,
+    removed: usize,
+    inserted: String,
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*pos)?;
+        *pos += 1;
+        n |= u64::from(b & 0x7f) << shift;
+        if b < 0x80 {
+            return usize::try_from(n).ok();
+        }
+    }
+    None
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        write_varint(&mut buf, self.start as u64);
+        write_varint(&mut buf, self.removed as u64);
+        write_varint(&mut buf, self.inserted.len() as u64);
+        buf.extend_from_slice(self.inserted.as_bytes());
+        buf
+    }
+
+    /// reads the record at `pos`, or None if it was not completely written.
+    fn decode(buf: &[u8], pos: &mut usize) -> Option<Self> {
+        let start = read_varint(buf, pos)?;
+        let removed = read_varint(buf, pos)?;
+        let len = read_varint(buf, pos)?;
+        let bytes = buf.get(*pos..pos.checked_add(len)?)?;
+        *pos += len;
+        let inserted = String::from_utf8(bytes.to_vec()).ok()?;
+        Some(Record { start, removed, inserted })
+    }
+
+    fn apply(&self, text: &Rope) -> Option<Rope> {
+        let end = self.start.checked_add(self.removed)?;
+        text.try_delete(self.start..end).ok()?
+            .try_insert(self.start, Rope::from(&*self.inserted)).ok()
+    }
+}
+
+/// the changes left in a swap file by an editor that did not close its
+/// buffer, which may have crashed, or may still be running.
+pub struct Recovery {
+    path: PathBuf,
+    pid: u32,
+    /// the hash of the file contents the changes apply to
+    base: u64,
+    records: Vec<Record>,
+}
+
+impl Recovery {
+    fn read(path: &Path) -> io::Result<Self> {
+        let buf = fs::read(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a swap file");
+        if buf.len() < 20 || &buf[..8] != MAGIC {
+            return Err(invalid());
+        }
+        let base = u64::from_le_bytes(<[u8; 8]>::try_from(&buf[8..16]).map_err(|_| invalid())?);
+        let pid = u32::from_le_bytes(<[u8; 4]>::try_from(&buf[16..20]).map_err(|_| invalid())?);
+
+        // a change that was being written when the editor died is lost
+        let mut records = vec![];
+        let mut pos = 20;
+        while let Some(record) = Record::decode(&buf, &mut pos) {
+            records.push(record);
+        }
+        Ok(Recovery { path: path.to_path_buf(), pid, base, records })
+    }
+
+    /// the id of the process that wrote the swap file
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// true if the process that wrote the swap file is still running, in
+    /// which case the file is open in another editor. this is always false
+    /// where it can't be checked.
+    pub fn is_running(&self) -> bool {
+        self.pid == process::id()
+            || (cfg!(target_os = "linux") && Path::new("/proc").join(self.pid.to_string()).exists())
+    }
+
+    /// the number of changes that can be recovered
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Buffer {
+    /// the changes found in a swap file when the buffer's file was loaded.
+    /// the buffer does not journal its own changes until these are
+    /// recovered or discarded.
+    pub fn recovery(&self) -> Option<&Recovery> {
+        self.recovery.as_ref()
+    }
+
+    /// replays the changes in the swap file over the file's contents, as
+    /// one edit that can be undone. fails if the file has changed since the
+    /// swap file was written.
+    pub fn recover(&mut self) -> io::Result<()> {
+        let recovery = self.recovery.as_ref().ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput, "there is no swap file to recover"))?;
+        let base = self.saved.map(|saved| self.history[saved].text.clone())
+            .filter(|base| hash_rope(base) == recovery.base)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData, "the swap file is for a different version of the file"))?;
+        let text = recovery.records.iter()
+            .try_fold(base.clone(), |text, record| record.apply(&text))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData, "the swap file does not fit the file"))?;
+
+        let delta = Delta::between(self.text(), &text);
+        let end = delta.start + delta.removed.len();
+        self.edit(delta.start, end, &delta.inserted, delta.start);
+        self.discard_recovery(&base)
+    }
+
+    /// deletes the swap file found when the buffer's file was loaded, and
+    /// starts journaling the buffer's own changes.
+    pub fn ignore_recovery(&mut self) -> io::Result<()> {
+        let base = match self.saved {
+            Some(saved) => self.history[saved].text.clone(),
+            None => self.text().clone(),
+        };
+        self.discard_recovery(&base)
+    }
+
+    fn discard_recovery(&mut self, disk: &Rope) -> io::Result<()> {
+        if let Some(recovery) = self.recovery.take() {
+            fs::remove_file(recovery.path)?;
+        }
+        self.start_swap(disk);
+        Ok(())
+    }
+
+    /// starts a new journal of the changes from `disk`, which is what the
+    /// buffer's file contains, to the current text. a swap file left by
+    /// another editor is kept for recovery instead.
+    pub(crate) fn start_swap(&mut self, disk: &Rope) {
+        self.close_swap();
+        let name = match &self.filename {
+            Some(name) => name,
+            None => return,
+        };
+        let path = swap_path(Path::new(name));
+        if self.recovery.is_some() {
+            return;
+        }
+        if let Ok(recovery) = Recovery::read(&path) {
+            self.recovery = Some(recovery);
+            return;
+        }
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&hash_rope(disk).to_le_bytes());
+        header.extend_from_slice(&process::id().to_le_bytes());
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&path)
+            .and_then(|mut file| file.write_all(&header).map(|_| file));
+        // editing goes on without a journal where one can't be written
+        if let Ok(file) = file {
+            self.swap = Some(Swap { file, path, synced: Instant::now() });
+            let delta = Delta::between(disk, self.text());
+            self.journal(delta.start, delta.removed.len(), &delta.inserted);
+        }
+    }
+
+    /// appends the replacement of `removed` bytes at `start` with
+    /// `inserted` to the journal.
+    pub(crate) fn journal(&mut self, start: usize, removed: usize, inserted: &str) {
+        if removed == 0 && inserted.is_empty() {
+            return;
+        }
+        let swap = match &mut self.swap {
+            Some(swap) => swap,
+            None => return,
+        };
+        let record = Record { start, removed, inserted: inserted.to_string() };
+        if swap.file.write_all(&record.encode()).is_err() {
+            self.close_swap();
+        } else if swap.synced.elapsed() >= SYNC_INTERVAL {
+            let _ = self.sync_swap();
+        }
+    }
+
+    /// makes sure every journaled change is on disk. changes are synced
+    /// at least every SYNC_INTERVAL as they are made, but the editor can
+    /// call this when it is idle to not leave the last ones waiting.
+    pub fn sync_swap(&mut self) -> io::Result<()> {
+        if let Some(swap) = &mut self.swap {
+            swap.file.sync_data()?;
+            swap.synced = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// stops journaling and deletes the swap file.
+    pub(crate) fn close_swap(&mut self) {
+        if let Some(swap) = self.swap.take() {
+            drop(swap.file);
+            let _ = fs::remove_file(swap.path);
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // a panic is a crash, so its changes are left to be recovered
+        if !thread::panicking() {
+            self.close_swap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::mem;
+    use std::path::Path;
+    use std::process;
+    use crate::swap::{Record, read_varint, swap_path, write_varint};
+    use crate::testutil::{load, temp_file};
+
+    /// edits the file at `path` and dies without closing the buffer.
+    fn crash(path: &Path) {
+        let mut b = load(path);
+        b.move_buffer_end();
+        b.insert_at_cursor("three\n");
+        b.move_buffer_start();
+        b.delete_forward();
+        b.insert_at_cursor("O");
+        b.undo();
+        b.undo();
+        b.redo();
+        assert_eq!(*b.text(), *"ne\ntwo\nthree\n");
+        mem::forget(b);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buf = vec![];
+        for &n in &[0, 127, 128, 300, u64::from(u32::MAX)] {
+            write_varint(&mut buf, n);
+        }
+        assert_eq!(&buf[..4], &[0, 127, 0x80, 1]);
+        let mut pos = 0;
+        let nums: Vec<_> = (0..5).map(|_| read_varint(&buf, &mut pos).unwrap()).collect();
+        assert_eq!(nums, vec![0, 127, 128, 300, u32::MAX as usize]);
+        assert_eq!(read_varint(&buf, &mut pos), None);
+        assert_eq!(read_varint(&[0x80], &mut 0), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let record = Record { start: 3, removed: 2, inserted: "xyz".to_string() };
+        let buf = record.encode();
+        let decoded = Record::decode(&buf, &mut 0).unwrap();
+        assert_eq!((decoded.start, decoded.removed, &*decoded.inserted), (3, 2, "xyz"));
+        assert!(Record::decode(&buf[..buf.len() - 1], &mut 0).is_none());
+    }
+
+    #[test]
+    fn test_recover() {
+        let (_dir, path) = temp_file("swap-recover", "one\ntwo\n");
+        crash(&path);
+
+        let mut b = load(&path);
+        let recovery = b.recovery().unwrap();
+        assert_eq!(recovery.pid(), process::id());
+        assert!(recovery.is_running());
+        assert_eq!(recovery.len(), 6);
+        assert_eq!(*b.text(), *"one\ntwo\n");
+
+        b.recover().unwrap();
+        assert!(b.recovery().is_none());
+        assert_eq!(*b.text(), *"ne\ntwo\nthree\n");
+        assert!(b.is_modified());
+        b.undo();
+        assert_eq!(*b.text(), *"one\ntwo\n");
+
+        // the recovered buffer journals its own changes
+        b.redo();
+        b.insert_at_cursor("o");
+        mem::forget(b);
+        let mut b = load(&path);
+        b.recover().unwrap();
+        assert_eq!(*b.text(), *"one\ntwo\nthree\n");
+
+        // a clean close leaves no swap file
+        b.save().unwrap();
+        drop(b);
+        assert!(!swap_path(&path).exists());
+    }
+
+    #[test]
+    fn test_recover_fails() {
+        let (_dir, path) = temp_file("swap-fails", "one\ntwo\n");
+        crash(&path);
+
+        // a change that was cut off is dropped
+        let mut swap = OpenOptions::new().append(true).open(swap_path(&path)).unwrap();
+        swap.write_all(&[0, 0, 10, b'x']).unwrap();
+        assert_eq!(load(&path).recovery().unwrap().len(), 6);
+
+        fs::write(&path, "one\ntwo!\n").unwrap();
+        let mut b = load(&path);
+        assert!(b.recover().is_err());
+        assert_eq!(*b.text(), *"one\ntwo!\n");
+
+        b.ignore_recovery().unwrap();
+        assert!(b.recovery().is_none());
+        b.insert_at_cursor("0");
+        mem::forget(b);
+        let mut b = load(&path);
+        assert_eq!(b.recovery().unwrap().len(), 1);
+        b.recover().unwrap();
+        assert_eq!(*b.text(), *"0one\ntwo!\n");
+    }
+}
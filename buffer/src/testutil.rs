@@ -0,0 +1,48 @@
+//! helpers for tests that need files on disk.
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::Buffer;
+
+/// a directory for the files of one test, removed with everything in it
+/// when dropped, so a failing test cleans up as well
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// makes an empty directory. `name` has to be unique among the tests.
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("pi-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// writes `text` to the file `name` in the directory
+    pub(crate) fn file(&self, name: &str, text: &str) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// a directory named `name` holding just `f.txt` with `text` in it. the
+/// file is gone once the directory is dropped.
+pub(crate) fn temp_file(name: &str, text: &str) -> (TempDir, PathBuf) {
+    let dir = TempDir::new(name);
+    let path = dir.file("f.txt", text);
+    (dir, path)
+}
+
+/// a buffer editing the file at `path`
+pub(crate) fn load(path: &Path) -> Buffer {
+    Buffer::new(Some(path.to_str().unwrap().to_string())).unwrap()
+}
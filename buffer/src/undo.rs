@@ -0,0 +1,545 @@
+//! undo and redo for a buffer.
+//!
+//! the history of a buffer is a tree of revisions, each holding a full
+//! snapshot of the text, which costs little since ropes share their
+//! unchanged parts. editing after an undo starts a new branch instead of
+//! throwing the undone edits away, so every revision can be returned to.
+//!
+//! runs of typed characters are merged into one revision, as are all the
+//! edits made between `begin_group` and `end_group`.
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime};
+use crate::Buffer;
+use crate::boundary::Direction;
+use crate::rope::Rope;
+use crate::selection::shift;
+
+/// how long typing can pause before the next character starts a new undo
+/// step
+pub const TYPING_PAUSE: Duration = Duration::from_secs(1);
+
+/// a version of the text, and where the cursor was around the edit that
+/// made it. revisions are numbered in the order they were made, and the
+/// original text is revision 0.
+#[derive(Clone, Debug)]
+pub(crate) struct Revision {
+    pub(crate) text: Rope,
+    /// the cursor before the edit, in the parent's text
+    pub(crate) before: usize,
+    /// the cursor after the edit
+    pub(crate) after: usize,
+    /// the edits that made the text from the parent's, in order
+    pub(crate) edits: Vec<Edit>,
+    /// when the edit was made
+    pub(crate) time: SystemTime,
+    pub(crate) parent: Option<usize>,
+    pub(crate) children: Vec<usize>,
+    /// the index in `children` that redo goes to
+    pub(crate) redo: usize,
+}
+
+impl Revision {
+    pub(crate) fn new(text: Rope) -> Self {
+        Revision {
+            text,
+            before: 0,
+            after: 0,
+            edits: vec![],
+            time: SystemTime::now(),
+            parent: None,
+            children: vec![],
+            redo: 0,
+        }
+    }
+}
+
+/// one edit, which replaced the bytes `start..end` with `len` new ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Edit {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) len: usize,
+}
+
+/// the bytes `edits` changed, as one edit that does the same
+fn span(edits: &[Edit]) -> Option<Edit> {
+    edits.iter().copied().reduce(|a, b| {
+        // the bytes a put in, and those between them and b
+        let until = (a.start + a.len).max(b.end);
+        let start = a.start.min(b.start);
+        Edit {
+            start,
+            end: a.end + until - (a.start + a.len),
+            len: until + b.len - (b.end - b.start) - start,
+        }
+    })
+}
+
+/// whether the next edit is merged into the newest revision
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Merge {
+    Off,
+    /// the newest revision is a run of typing, last added to at this time
+    Typing(Instant),
+    /// the newest revision was made by the group being built
+    Group,
+}
+
+/// true if inserting `text` could be part of a run of typing
+fn is_typed(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some() && chars.next().is_none()
+}
+
+impl Buffer {
+    /// reverts the current revision, putting the cursor back where it was
+    /// before its edit. returns false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history[self.current].parent {
+            Some(parent) => self.goto_revision(parent),
+            None => false,
+        }
+    }
+
+    /// reapplies the edit on the selected branch after the current revision.
+    /// returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let rev = &self.history[self.current];
+        match rev.children.get(rev.redo) {
+            Some(&child) => self.goto_revision(child),
+            None => false,
+        }
+    }
+
+    /// selects which of the branches after the current revision redo goes
+    /// to, moving to the next newer or older one. returns false if there is
+    /// no branch in that direction.
+    pub fn select_branch(&mut self, dir: Direction) -> bool {
+        let rev = &mut self.history[self.current];
+        let redo = match dir {
+            Direction::Forward if rev.redo + 1 < rev.children.len() => rev.redo + 1,
+            Direction::Backward if rev.redo > 0 => rev.redo - 1,
+            _ => return false,
+        };
+        rev.redo = redo;
+        true
+    }
+
+    /// the number of the current revision
+    pub fn revision(&self) -> usize {
+        self.current
+    }
+
+    /// moves to revision `seq`, on any branch. redo will follow the path to
+    /// it from the original text from now on. returns false if there is no
+    /// such revision, or it is already the current one.
+    pub fn goto_revision(&mut self, seq: usize) -> bool {
+        if seq >= self.history.len() || seq == self.current {
+            return false;
+        }
+        self.interrupt_substitute();
+
+        // going back to an ancestor is an undo, which leaves the cursor
+        // where the first undone edit was made
+        let mut below = None;
+        let mut rev = self.current;
+        while let Some(parent) = self.history[rev].parent {
+            if parent == seq {
+                below = Some(rev);
+                break;
+            }
+            rev = parent;
+        }
+        let cursor = match below {
+            Some(rev) => self.history[rev].before,
+            None => self.history[seq].after,
+        };
+
+        let mut rev = seq;
+        while let Some(parent) = self.history[rev].parent {
+            let p = &mut self.history[parent];
+            p.redo = p.children.iter().position(|&c| c == rev).unwrap_or(0);
+            rev = parent;
+        }
+
+        // the path goes up from the current revision to the nearest one it
+        // shares with seq, and down from there. parents come before children
+        let (mut up, mut down) = (vec![], vec![]);
+        let (mut a, mut b) = (self.current, seq);
+        while a != b {
+            if a > b {
+                up.push(a);
+                a = self.history[a].parent.unwrap_or(0);
+            } else {
+                down.push(b);
+                b = self.history[b].parent.unwrap_or(0);
+            }
+        }
+        for rev in up {
+            let undone = self.history[rev].edits.iter().rev()
+                .map(|e| Edit { start: e.start, end: e.start + e.len, len: e.end - e.start })
+                .collect();
+            self.current = self.history[rev].parent.unwrap_or(0);
+            self.edits_made(undone);
+        }
+        for rev in down.into_iter().rev() {
+            self.current = rev;
+            self.edits_made(self.history[rev].edits.clone());
+        }
+        self.merge = Merge::Off;
+        self.note_change();
+        self.set_cursor(cursor);
+        true
+    }
+
+    /// journals the edits that were just made to reach the current text,
+    /// and moves the marks and search matches through them.
+    pub(crate) fn edits_made(&mut self, edits: Vec<Edit>) {
+        if let Some(e) = span(&edits).filter(|_| self.swap.is_some()) {
+            let inserted: String = self.text().char_slice(e.start..e.start + e.len)
+                .str_iter().collect();
+            self.journal(e.start, e.end - e.start, &inserted);
+        }
+        // the matches are only searched for again once the text is there
+        let mut changed: Option<Range<usize>> = None;
+        for e in edits {
+            self.shift_marks(e.start, e.end, e.len);
+            let moved = self.move_search(e.start, e.end, e.len);
+            changed = Some(match changed {
+                Some(c) => {
+                    let c = shift(c.start, e.start, e.end, e.len)..shift(c.end, e.start, e.end, e.len);
+                    c.start.min(moved.start)..c.end.max(moved.end)
+                },
+                None => moved,
+            });
+        }
+        if let Some(changed) = changed {
+            self.rescan_matches(changed);
+        }
+    }
+
+    /// goes back to the newest revision made at least `d` before the
+    /// current one, or to the original text if there is none, like vim's
+    /// `:earlier`. returns false if already at the original text.
+    pub fn earlier(&mut self, d: Duration) -> bool {
+        let now = self.history[self.current].time;
+        let seq = now.checked_sub(d)
+            .and_then(|target| self.history[..self.current]
+                      .iter()
+                      .rposition(|rev| rev.time <= target))
+            .unwrap_or(0);
+        self.goto_revision(seq)
+    }
+
+    /// goes forward to the newest revision made at most `d` after the
+    /// current one, like vim's `:later`. returns false if there is none.
+    pub fn later(&mut self, d: Duration) -> bool {
+        let target = self.history[self.current].time.checked_add(d);
+        let seq = self.history[self.current + 1..]
+            .iter()
+            .rposition(|rev| target.is_none_or(|target| rev.time <= target));
+        match seq {
+            Some(i) => self.goto_revision(self.current + 1 + i),
+            None => false,
+        }
+    }
+
+    /// starts a group of edits that are undone together, for commands that
+    /// make several edits. groups can be nested, and only the outermost one
+    /// counts.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.merge = Merge::Off;
+        }
+        self.group_depth += 1;
+    }
+
+    /// ends the group started by the matching begin_group.
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.merge = Merge::Off;
+        }
+    }
+
+    /// ends a group like end_group, but if it made a revision, the typing
+    /// that follows is added to it, for edits that only make room for what
+    /// is typed next.
+    pub(crate) fn end_group_typing(&mut self) {
+        let made = self.merge == Merge::Group;
+        self.end_group();
+        if made && self.group_depth == 0 {
+            self.merge = Merge::Typing(Instant::now());
+        }
+    }
+
+    /// begin_group for one key of typing `text` at several cursors, which
+    /// joins the run of typing before it.
+    pub(crate) fn begin_typing(&mut self, text: &str) {
+        let joins = self.group_depth == 0 && is_typed(text)
+            && matches!(self.merge, Merge::Typing(last) if last.elapsed() < TYPING_PAUSE);
+        self.begin_group();
+        if joins {
+            self.merge = Merge::Group;
+        }
+    }
+
+    /// ends the group started by begin_typing, leaving the run of typing
+    /// open for the next key.
+    pub(crate) fn end_typing(&mut self, text: &str) {
+        self.end_group();
+        if self.group_depth == 0 && is_typed(text) && !text.chars().all(char::is_whitespace) {
+            self.merge = Merge::Typing(Instant::now());
+        }
+    }
+
+    /// stops the current run of typing, so the next character typed starts
+    /// a new undo step. called by every cursor motion.
+    pub(crate) fn break_typing(&mut self) {
+        if let Merge::Typing(_) = self.merge {
+            self.merge = Merge::Off;
+        }
+    }
+
+    /// makes `text` the current revision, made by `edits`. `inserted` is
+    /// the text a single edit inserted, and `before` and `after` are the
+    /// cursor around them.
+    pub(crate) fn record(&mut self, text: Rope, edits: &[Edit], inserted: &str,
+                         before: usize, after: usize) {
+        let typed = self.group_depth == 0 && edits.len() == 1 && is_typed(inserted);
+        // the saved revision has to stay as it was saved
+        let merge = self.saved != Some(self.current) && match self.merge {
+            Merge::Off => false,
+            Merge::Typing(last) => typed && last.elapsed() < TYPING_PAUSE,
+            Merge::Group => true,
+        };
+
+        if merge {
+            let rev = &mut self.history[self.current];
+            for &e in edits {
+                match rev.edits.last_mut() {
+                    // typing adds to the end of the last insert
+                    Some(last) if e.start == e.end && e.start == last.start + last.len => {
+                        last.len += e.len;
+                    },
+                    _ => rev.edits.push(e),
+                }
+            }
+            rev.text = text;
+            rev.after = after;
+            rev.time = SystemTime::now();
+        } else {
+            let seq = self.history.len();
+            let parent = &mut self.history[self.current];
+            parent.children.push(seq);
+            parent.redo = parent.children.len() - 1;
+            self.history.push(Revision {
+                before,
+                after,
+                edits: edits.to_vec(),
+                parent: Some(self.current),
+                ..Revision::new(text)
+            });
+            self.current = seq;
+        }
+
+        self.merge = if self.group_depth > 0 {
+            Merge::Group
+        } else if typed && !inserted.chars().all(char::is_whitespace) {
+            Merge::Typing(Instant::now())
+        } else {
+            Merge::Off
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+    use crate::Buffer;
+    use crate::boundary::Direction;
+    use crate::rope::Rope;
+    use crate::undo::{Edit, Merge, TYPING_PAUSE, span};
+
+    fn type_str(b: &mut Buffer, s: &str) {
+        for ch in s.chars() {
+            b.insert_at_cursor(ch.encode_utf8(&mut [0; 4]));
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut b = Buffer::from(Rope::from("abc\ndef"));
+        b.set_cursor(5);
+        b.delete_range(1..5).unwrap();
+        b.move_buffer_end();
+        b.insert_newline();
+        assert_eq!(*b.text(), *"aef\n");
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"aef");
+        assert_eq!(b.cursor(), 3);
+        assert!(b.undo());
+        assert_eq!(*b.text(), *"abc\ndef");
+        assert_eq!(b.cursor(), 5);
+        assert!(!b.undo());
+        assert!(b.redo());
+        assert_eq!(*b.text(), *"aef");
+        assert_eq!(b.cursor(), 1);
+        assert!(b.redo());
+        assert_eq!(b.cursor(), 4);
+        assert!(!b.redo());
+
+        // editing after an undo starts a new branch, which redo follows
+        b.undo();
+        b.insert_at_cursor("!");
+        assert!(!b.redo());
+        assert_eq!(*b.text(), *"aef!");
+        b.undo();
+        b.redo();
+        assert_eq!(*b.text(), *"aef!");
+    }
+
+    #[test]
+    fn test_typing_runs() {
+        let mut b = Buffer::from(Rope::from(""));
+        type_str(&mut b, "foo  bar");
+        b.move_left();
+        type_str(&mut b, "ba");
+        assert_eq!(*b.text(), *"foo  babar");
+        b.undo();
+        assert_eq!(*b.text(), *"foo  bar");
+        assert_eq!(b.cursor(), 7);
+        b.undo();
+        assert_eq!(*b.text(), *"foo  ");
+        b.undo();
+        assert_eq!(*b.text(), *"foo ");
+        b.undo();
+        assert_eq!(*b.text(), *"");
+        assert_eq!(b.cursor(), 0);
+        b.redo();
+        assert_eq!(*b.text(), *"foo ");
+        assert_eq!(b.cursor(), 4);
+    }
+
+    #[test]
+    fn test_typing_pause() {
+        let mut b = Buffer::from(Rope::from(""));
+        type_str(&mut b, "ab");
+        let long_ago = Instant::now().checked_sub(TYPING_PAUSE * 2).unwrap();
+        b.merge = Merge::Typing(long_ago);
+        type_str(&mut b, "cd");
+        b.undo();
+        assert_eq!(*b.text(), *"ab");
+    }
+
+    #[test]
+    fn test_span() {
+        let edit = |start, end, len| Edit { start, end, len };
+        assert_eq!(span(&[]), None);
+        assert_eq!(span(&[edit(2, 4, 1)]), Some(edit(2, 4, 1)));
+        assert_eq!(span(&[edit(12, 12, 1), edit(1, 1, 1)]), Some(edit(1, 12, 13)));
+        assert_eq!(span(&[edit(1, 3, 0), edit(4, 6, 3)]), Some(edit(1, 8, 6)));
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut b = Buffer::from(Rope::from("one\ntwo\n"));
+        type_str(&mut b, "x");
+        b.begin_group();
+        b.join_lines();
+        b.begin_group();
+        type_str(&mut b, "yz");
+        b.end_group();
+        b.move_buffer_start();
+        b.delete_forward();
+        b.end_group();
+        type_str(&mut b, "w");
+        assert_eq!(*b.text(), *"woneyz two\n");
+        b.undo();
+        b.undo();
+        assert_eq!(*b.text(), *"xone\ntwo\n");
+        assert_eq!(b.cursor(), 1);
+        b.redo();
+        assert_eq!(*b.text(), *"oneyz two\n");
+        assert_eq!(b.cursor(), 0);
+
+        // unbalanced ends are ignored
+        b.end_group();
+        type_str(&mut b, "v");
+        b.undo();
+        assert_eq!(*b.text(), *"oneyz two\n");
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut b = Buffer::from(Rope::from("x"));
+        b.insert_at_cursor("a ");
+        b.undo();
+        b.insert_at_cursor("b ");
+        b.undo();
+        b.insert_at_cursor("c ");
+        b.undo();
+        assert_eq!(b.history.len(), 4);
+
+        // redo follows the newest branch until another is selected
+        b.redo();
+        assert_eq!(*b.text(), *"c x");
+        b.undo();
+        assert!(b.select_branch(Direction::Backward));
+        assert!(b.select_branch(Direction::Backward));
+        assert!(!b.select_branch(Direction::Backward));
+        b.redo();
+        assert_eq!(*b.text(), *"a x");
+        b.undo();
+        assert!(b.select_branch(Direction::Forward));
+        b.redo();
+        assert_eq!(*b.text(), *"b x");
+
+        // jumping to a revision makes redo follow the path to it
+        b.insert_at_cursor("d ");
+        b.goto_revision(3);
+        assert_eq!(*b.text(), *"c x");
+        assert_eq!(b.revision(), 3);
+        b.goto_revision(0);
+        b.redo();
+        assert_eq!(*b.text(), *"c x");
+        b.goto_revision(4);
+        b.undo();
+        b.undo();
+        b.redo();
+        b.redo();
+        assert_eq!(*b.text(), *"b d x");
+        assert!(!b.goto_revision(4));
+        assert!(!b.goto_revision(5));
+    }
+
+    #[test]
+    fn test_earlier_later() {
+        let mut b = Buffer::from(Rope::from(""));
+        for s in &["a", "b", "c", "d"] {
+            b.insert_at_cursor(s);
+            b.move_buffer_start();
+        }
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        for (rev, secs) in b.history.iter_mut().zip(&[0, 10, 20, 50, 60]) {
+            rev.time = start + Duration::from_secs(*secs);
+        }
+
+        assert!(b.earlier(Duration::from_secs(5)));
+        assert_eq!(*b.text(), *"cba");
+        assert!(b.earlier(Duration::from_secs(30)));
+        assert_eq!(*b.text(), *"ba");
+        assert!(b.earlier(Duration::from_secs(1000)));
+        assert_eq!(*b.text(), *"");
+        assert!(!b.earlier(Duration::from_secs(1)));
+
+        assert!(b.later(Duration::from_secs(25)));
+        assert_eq!(*b.text(), *"ba");
+        assert!(!b.later(Duration::from_secs(25)));
+        assert!(b.later(Duration::from_secs(35)));
+        assert_eq!(*b.text(), *"cba");
+        assert!(b.later(Duration::from_secs(1000)));
+        assert_eq!(*b.text(), *"dcba");
+        assert!(!b.later(Duration::from_secs(1)));
+    }
+}
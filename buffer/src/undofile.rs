@@ -0,0 +1,320 @@
+//! keeps the undo history of a buffer in a file next to the one it edits,
+//! so it can be restored the next time that file is loaded.
+//!
+//! the undo file holds the hash of the text it was written for, and for
+//! every revision the edit that made it from its parent. the texts are
+//! rebuilt from the file being loaded, so the undo file is only used if
+//! that file still hashes the same.
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use crate::{Buffer, no_file_error};
+use crate::hash::hash_rope;
+use crate::rope::Rope;
+use crate::undo::{Edit, Merge, Revision};
+
+const MAGIC: &[u8; 8] = b"piundo\x00\x01";
+
+/// the undo file for the file at `path`, which is `.name.un~` in the same
+/// directory, like vim's.
+pub fn undo_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".un~");
+    path.with_file_name(name)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// the edit that turns one text into another, by replacing `removed` at
+/// `start` with `inserted`.
+#[derive(Default)]
+pub(crate) struct Delta {
+    pub(crate) start: usize,
+    pub(crate) removed: String,
+    pub(crate) inserted: String,
+}
+
+impl Delta {
+    /// the smallest delta that turns `old` into `new`.
+    pub(crate) fn between(old: &Rope, new: &Rope) -> Self {
+        let start = old.chars_from(0)
+            .zip(new.chars_from(0))
+            .take_while(|((_, a), (_, b))| a == b)
+            .last()
+            .map_or(0, |((i, ch), _)| i + ch.len_utf8());
+
+        let (mut old_end, mut new_end) = (old.len(), new.len());
+        for ((i, a), (j, b)) in old.chars_before(old.len()).zip(new.chars_before(new.len())) {
+            if a != b || i < start || j < start {
+                break;
+            }
+            old_end = i;
+            new_end = j;
+        }
+
+        Delta {
+            start,
+            removed: old.char_slice(start..old_end).str_iter().collect(),
+            inserted: new.char_slice(start..new_end).str_iter().collect(),
+        }
+    }
+
+    fn replace(text: &Rope, start: usize, old: &str, new: &str) -> io::Result<Rope> {
+        let end = start.saturating_add(old.len());
+        if end > text.len() {
+            return Err(invalid("delta out of range"));
+        }
+        text.try_delete(start..end)
+            .and_then(|text| text.try_insert(start, Rope::from(new)))
+            .map_err(|_| invalid("undo file does not fit the text"))
+    }
+
+    fn apply(&self, text: &Rope) -> io::Result<Rope> {
+        Delta::replace(text, self.start, &self.removed, &self.inserted)
+    }
+
+    fn revert(&self, text: &Rope) -> io::Result<Rope> {
+        Delta::replace(text, self.start, &self.inserted, &self.removed)
+    }
+}
+
+fn write_u64(w: &mut impl Write, n: u64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_str(w: &mut impl Write, s: &str) -> io::Result<()> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_usize(r: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(read_u64(r)?).map_err(|_| invalid("number too large"))
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u64(r)?;
+    let mut buf = vec![];
+    r.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|_| invalid("text is not utf-8"))
+}
+
+impl Buffer {
+    /// writes the undo history to the undo file of the buffer's file. this
+    /// should be done once the current text has been written to that file.
+    pub fn write_undo_file(&self) -> io::Result<()> {
+        let name = self.filename.as_ref().ok_or_else(no_file_error)?;
+        let mut w = BufWriter::new(File::create(undo_path(Path::new(name)))?);
+
+        w.write_all(MAGIC)?;
+        write_u64(&mut w, hash_rope(self.text()))?;
+        write_u64(&mut w, self.history.len() as u64)?;
+        write_u64(&mut w, self.current as u64)?;
+        for rev in &self.history {
+            let delta = match rev.parent {
+                Some(p) => Delta::between(&self.history[p].text, &rev.text),
+                None => Delta::default(),
+            };
+            let time = rev.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+            write_u64(&mut w, rev.parent.map_or(u64::MAX, |p| p as u64))?;
+            write_u64(&mut w, rev.before as u64)?;
+            write_u64(&mut w, rev.after as u64)?;
+            write_u64(&mut w, time.as_secs())?;
+            write_u64(&mut w, u64::from(time.subsec_nanos()))?;
+            write_u64(&mut w, rev.redo as u64)?;
+            write_u64(&mut w, delta.start as u64)?;
+            write_str(&mut w, &delta.removed)?;
+            write_str(&mut w, &delta.inserted)?;
+        }
+        w.flush()
+    }
+
+    /// replaces the history with the one in the undo file of the buffer's
+    /// file, if that was written for the current text. on failure the
+    /// history is left alone.
+    pub(crate) fn read_undo_file(&mut self) -> io::Result<()> {
+        let name = self.filename.as_ref().ok_or_else(no_file_error)?;
+        let mut r = BufReader::new(File::open(undo_path(Path::new(name)))?);
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an undo file"));
+        }
+        if read_u64(&mut r)? != hash_rope(self.text()) {
+            return Err(invalid("undo file is for a different text"));
+        }
+        let len = read_usize(&mut r)?;
+        let current = read_usize(&mut r)?;
+        if current >= len {
+            return Err(invalid("current revision does not exist"));
+        }
+
+        let mut history: Vec<Revision> = vec![];
+        let mut deltas = vec![];
+        for seq in 0..len {
+            let parent = match read_u64(&mut r)? {
+                u64::MAX if seq == 0 => None,
+                p if seq > 0 && p < seq as u64 => Some(p as usize),
+                _ => return Err(invalid("revisions are out of order")),
+            };
+            let before = read_usize(&mut r)?;
+            let after = read_usize(&mut r)?;
+            let secs = Duration::from_secs(read_u64(&mut r)?);
+            let nanos = Duration::from_nanos(read_u64(&mut r)?);
+            let time = UNIX_EPOCH.checked_add(secs)
+                .and_then(|t| t.checked_add(nanos))
+                .ok_or_else(|| invalid("time out of range"))?;
+            let redo = read_usize(&mut r)?;
+            let delta = Delta {
+                start: read_usize(&mut r)?,
+                removed: read_str(&mut r)?,
+                inserted: read_str(&mut r)?,
+            };
+
+            let end = delta.start.checked_add(delta.removed.len())
+                .ok_or_else(|| invalid("delta out of range"))?;
+
+            if let Some(p) = parent {
+                history[p].children.push(seq);
+            }
+            history.push(Revision {
+                before,
+                after,
+                edits: vec![Edit { start: delta.start, end, len: delta.inserted.len() }],
+                time,
+                parent,
+                redo,
+                ..Revision::new(Rope::from(""))
+            });
+            deltas.push(delta);
+        }
+
+        // the current text is known, so work back from it to the original
+        // text, and then forward to every other revision
+        let mut known = vec![false; len];
+        history[current].text = self.text().clone();
+        known[current] = true;
+        let mut seq = current;
+        while let Some(p) = history[seq].parent {
+            history[p].text = deltas[seq].revert(&history[seq].text)?;
+            known[p] = true;
+            seq = p;
+        }
+        for seq in 0..len {
+            if let (false, Some(p)) = (known[seq], history[seq].parent) {
+                history[seq].text = deltas[seq].apply(&history[p].text)?;
+            }
+        }
+
+        self.history = history;
+        self.current = current;
+        self.merge = Merge::Off;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::boundary::Direction;
+    use crate::hash::hash_rope;
+    use crate::rope::Rope;
+    use crate::testutil::{load, temp_file};
+    use crate::undofile::{Delta, MAGIC, undo_path, write_str, write_u64};
+
+    #[test]
+    fn test_undo_path() {
+        assert_eq!(undo_path(Path::new("/a/b.txt")), Path::new("/a/.b.txt.un~"));
+        assert_eq!(undo_path(Path::new("b")), Path::new(".b.un~"));
+    }
+
+    #[test]
+    fn test_delta() {
+        let old = Rope::concat(&Rope::from("hello "), &Rope::from("wörld"));
+        let new = Rope::from("help wörld");
+        let delta = Delta::between(&old, &new);
+        assert_eq!((delta.start, &*delta.removed, &*delta.inserted), (3, "lo", "p"));
+        assert_eq!(delta.apply(&old).unwrap(), new);
+        assert_eq!(delta.revert(&new).unwrap(), old);
+
+        // the common suffix can't overlap the common prefix
+        let delta = Delta::between(&Rope::from("aa"), &Rope::from("aaa"));
+        assert_eq!((delta.start, &*delta.removed, &*delta.inserted), (2, "", "a"));
+        assert!(delta.revert(&Rope::from("a")).is_err());
+    }
+
+    #[test]
+    fn test_restore_history() {
+        let (_dir, path) = temp_file("undofile-restore", "one\ntwo\n");
+        let mut b = load(&path);
+        b.insert_at_cursor("zero\n");
+        b.undo();
+        b.join_lines();
+        b.move_buffer_end();
+        b.insert_at_cursor("three\n");
+        b.undo();
+        fs::write(&path, "one two\n").unwrap();
+        b.write_undo_file().unwrap();
+
+        let mut c = load(&path);
+        assert_eq!(c.history.len(), 4);
+        assert_eq!(c.revision(), 2);
+        assert_eq!(c.history[1].time, b.history[1].time);
+        c.redo();
+        assert_eq!(*c.text(), *"one two\nthree\n");
+        c.undo();
+        c.undo();
+        assert_eq!(*c.text(), *"one\ntwo\n");
+        assert_eq!(c.cursor(), 0);
+        c.select_branch(Direction::Backward);
+        c.redo();
+        assert_eq!(*c.text(), *"zero\none\ntwo\n");
+        assert_eq!(c.cursor(), 5);
+
+        // a changed file makes the undo file useless
+        fs::write(&path, "one two\n!").unwrap();
+        let c = load(&path);
+        assert_eq!(c.history.len(), 1);
+    }
+
+    #[test]
+    fn test_corrupt_undo_file() {
+        let (_dir, path) = temp_file("undofile-corrupt", "abc");
+        for start in [u64::MAX - 1, 2].iter() {
+            let mut w = MAGIC.to_vec();
+            for n in [hash_rope(&Rope::from("abc")), 2, 1].iter() {
+                write_u64(&mut w, *n).unwrap();
+            }
+            for (parent, start) in [(u64::MAX, 0), (0, *start)].iter() {
+                for n in [*parent, 0, 0, 0, 0, 0, *start].iter() {
+                    write_u64(&mut w, *n).unwrap();
+                }
+                write_str(&mut w, "xyz").unwrap();
+                write_str(&mut w, "xyz").unwrap();
+            }
+            fs::write(undo_path(&path), w).unwrap();
+
+            // a delta past the end of the text is an error, not a panic
+            let mut b = load(&path);
+            assert!(b.read_undo_file().is_err());
+            assert_eq!(b.history.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,542 @@
+//! vim's visual mode, and the operators that work on what it selects.
+//!
+//! visual mode grows the primary selection as the cursor moves. charwise
+//! selections include the character under the head, as in vim, and
+//! linewise ones every line they touch. blockwise selections are the
+//! rectangle of display columns between the anchor and the head, on each of
+//! their lines. tabs that cross an edge of a block are split into spaces
+//! when the block is edited, so the columns beside it stay where they are.
+use std::ops::Range;
+use crate::Buffer;
+use crate::column::char_width;
+use crate::rope::Rope;
+use crate::selection::Selection;
+
+/// how a visual selection covers the text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualMode {
+    Char,
+    Line,
+    Block,
+}
+
+/// the part of a line inside of a block
+struct BlockPart {
+    start: usize,
+    end: usize,
+    /// the columns of the character at `start` that are left of the block
+    before: usize,
+    /// the columns of the character before `end` that are right of the block
+    after: usize,
+    /// the text in the block, with the parts of split characters as spaces
+    text: String,
+}
+
+fn spaces(n: usize) -> String {
+    " ".repeat(n)
+}
+
+/// finds the part of line `lnum` between display columns `left` and
+/// `right`, or None if the line ends before the block.
+fn block_part(text: &Rope, lnum: usize, left: usize, right: usize, tab_width: usize) -> Option<BlockPart> {
+    let mut part: Option<BlockPart> = None;
+    let mut end = text.line_end(lnum);
+    let mut col = 0;
+    for (i, ch) in text.chars_from(text.line_start(lnum)) {
+        let width = char_width(ch, col, tab_width);
+        if ch == '\n' || (col >= right && width > 0) {
+            end = i;
+            break;
+        }
+        if part.is_some() || col + width > left {
+            let part = part.get_or_insert_with(|| BlockPart {
+                start: i,
+                end: i,
+                before: left.saturating_sub(col),
+                after: 0,
+                text: String::new(),
+            });
+            if col >= left && col + width <= right {
+                part.text.push(ch);
+            } else {
+                part.text.push_str(&spaces((col + width).min(right) - col.max(left)));
+            }
+            if col + width > right {
+                part.after = col + width - right;
+                end = i + ch.len_utf8();
+                break;
+            }
+        }
+        col += width;
+    }
+    part.map(|part| BlockPart { end, ..part })
+}
+
+impl Buffer {
+    /// the kind of visual selection being made, or None outside of visual
+    /// mode
+    pub fn visual_mode(&self) -> Option<VisualMode> {
+        self.visual
+    }
+
+    /// starts visual mode, or switches to `mode` if it is already on. the
+    /// selection starts at the cursor, or keeps its anchor if the primary
+    /// selection is not empty. other selections are dropped.
+    pub fn start_visual(&mut self, mode: VisualMode) {
+        self.others.clear();
+        self.visual = Some(mode);
+        self.extending = true;
+    }
+
+    /// leaves visual mode, leaving just the cursor.
+    pub fn exit_visual(&mut self) {
+        self.visual = None;
+        self.extending = false;
+        self.collapse_selections();
+    }
+
+    /// copies the visual selection, leaving visual mode with the cursor at
+    /// its start. linewise text always ends with a newline, and the lines
+    /// of a block are joined with them. returns None outside of visual mode.
+    pub fn visual_yank(&mut self) -> Option<Rope> {
+        let mode = self.visual?;
+        let yanked = self.visual_text(mode);
+        let start = self.visual_start(mode);
+        self.exit_visual();
+        self.set_cursor(start);
+        Some(yanked)
+    }
+
+    /// deletes the visual selection as one undo step, leaving visual mode.
+    /// returns the text deleted, like visual_yank.
+    pub fn visual_delete(&mut self) -> Option<Rope> {
+        let mode = self.visual?;
+        let deleted = self.visual_text(mode);
+        let start = self.visual_start(mode);
+        let (first, last) = self.visual_lines();
+        let text = self.text();
+        // the last line takes the newline before it with it
+        let (range, lnum) = if mode == VisualMode::Line && last >= text.lenlines() && first > 0 {
+            (text.line_end(first - 1)..text.len(), first - 1)
+        } else {
+            (self.visual_range(mode, first, last), first)
+        };
+        let parts = if mode == VisualMode::Block { self.block_parts() } else { vec![] };
+        self.exit_visual();
+
+        match mode {
+            VisualMode::Char => self.edit(range.start, range.end, "", range.start),
+            VisualMode::Line => {
+                self.edit(range.start, range.end, "", range.start);
+                let idx = self.first_non_blank(lnum);
+                self.set_cursor(idx);
+            },
+            VisualMode::Block => {
+                // from the bottom up, so the parts left to delete stay put
+                self.begin_group();
+                for part in parts.into_iter().rev().flatten() {
+                    self.edit(part.start, part.end, &spaces(part.before + part.after), start);
+                }
+                self.end_group();
+                self.set_cursor(start);
+            },
+        }
+        Some(deleted)
+    }
+
+    /// deletes the visual selection like visual_delete, and leaves the
+    /// cursor where text can be typed in its place. a linewise change keeps
+    /// one line, with the indentation of the first, and a block change
+    /// leaves a cursor on every line that reached the block, with what is
+    /// typed there undone together with the change.
+    pub fn visual_change(&mut self) -> Option<Rope> {
+        let mode = self.visual?;
+        let (first, last) = self.visual_lines();
+        match mode {
+            VisualMode::Char => self.visual_delete(),
+            VisualMode::Line => {
+                let changed = self.visual_text(mode);
+                self.exit_visual();
+                let text = self.text();
+                let start = text.line_start(first);
+                let indent: String = text.char_slice(start..self.first_non_blank(first))
+                    .str_iter()
+                    .collect();
+                let end = text.line_end(last);
+                self.edit(start, end, &indent, start + indent.len());
+                Some(changed)
+            },
+            VisualMode::Block => {
+                let (left, _) = self.block_columns();
+                let lines: Vec<_> = (first..=last).zip(self.block_parts())
+                    .filter(|(_, part)| part.is_some())
+                    .map(|(lnum, _)| lnum)
+                    .collect();
+                self.begin_group();
+                let changed = self.visual_delete();
+                let cursors = self.cursors_at_column(&lines, left);
+                self.set_cursors(cursors);
+                self.end_group_typing();
+                changed
+            },
+        }
+    }
+
+    /// leaves visual mode with the cursor where vim's `I` inserts: at the
+    /// start of a charwise selection, the first non-blank character of a
+    /// linewise one, or the left edge of a block, on every line of it that
+    /// reaches that far. returns false outside of visual mode.
+    pub fn visual_insert(&mut self) -> bool {
+        let mode = match self.visual {
+            Some(mode) => mode,
+            None => return false,
+        };
+        let (first, last) = self.visual_lines();
+        let (left, _) = self.block_columns();
+        let start = self.visual_start(mode);
+        self.exit_visual();
+
+        match mode {
+            VisualMode::Char => self.set_cursor(start),
+            VisualMode::Line => {
+                let idx = self.first_non_blank(first);
+                self.set_cursor(idx);
+            },
+            VisualMode::Block => {
+                let lines: Vec<_> = (first..=last)
+                    .filter(|&lnum| block_part(self.text(), lnum, left, usize::MAX, self.tab_width).is_some())
+                    .collect();
+                self.begin_group();
+                let cursors = self.cursors_at_column(&lines, left);
+                self.set_cursors(cursors);
+                self.end_group_typing();
+            },
+        }
+        true
+    }
+
+    /// leaves visual mode with the cursor where vim's `A` appends: after a
+    /// charwise selection, at the end of the last line of a linewise one, or
+    /// at the right edge of a block on every line of it. short lines are
+    /// padded with spaces to reach the block, as part of the undo step of
+    /// what is typed next, unless it was made with `$`, when every cursor
+    /// goes to the end of its line. returns false outside of visual mode.
+    pub fn visual_append(&mut self) -> bool {
+        let mode = match self.visual {
+            Some(mode) => mode,
+            None => return false,
+        };
+        let (first, last) = self.visual_lines();
+        let (_, right) = self.block_columns();
+        let end = self.visual_range(mode, first, last).end;
+        self.exit_visual();
+
+        match mode {
+            VisualMode::Char => self.set_cursor(end),
+            VisualMode::Line => {
+                let idx = self.text().line_end(last);
+                self.set_cursor(idx);
+            },
+            VisualMode::Block if right == usize::MAX => {
+                let cursors = (first..=last).map(|lnum| self.text().line_end(lnum)).collect();
+                self.set_cursors(cursors);
+            },
+            VisualMode::Block => {
+                self.begin_group();
+                for lnum in (first..=last).rev() {
+                    let end = self.text().line_end(lnum);
+                    let width = self.text().column_at(end, self.tab_width);
+                    if width < right {
+                        self.edit(end, end, &spaces(right - width), end);
+                    }
+                }
+                let lines: Vec<_> = (first..=last).collect();
+                let cursors = self.cursors_at_column(&lines, right);
+                self.set_cursors(cursors);
+                self.end_group_typing();
+            },
+        }
+        true
+    }
+
+    /// the first and last lines of the visual selection
+    pub(crate) fn visual_lines(&self) -> (usize, usize) {
+        let sel = self.selection();
+        (self.text().line_at(sel.start()), self.text().line_at(sel.end()))
+    }
+
+    /// the display columns of the left edge of the block, and of the column
+    /// just right of it, which is usize::MAX if the block goes to the end of
+    /// every line.
+    fn block_columns(&self) -> (usize, usize) {
+        let text = self.text();
+        let (anchor, head) = (self.anchor, self.cursor());
+        let cols = (text.column_at(anchor, self.tab_width), text.column_at(head, self.tab_width));
+        if self.goal_col == Some(usize::MAX) {
+            return (cols.0.min(cols.1), usize::MAX);
+        }
+
+        // the block includes all of the character in its last column
+        let (col, idx) = if cols.0 >= cols.1 { (cols.0, anchor) } else { (cols.1, head) };
+        let width = match text.chars_from(idx).next() {
+            Some((_, ch)) if ch != '\n' => char_width(ch, col, self.tab_width).max(1),
+            _ => 1,
+        };
+        (cols.0.min(cols.1), col + width)
+    }
+
+    /// the bytes a charwise or linewise selection covers
+    pub(crate) fn visual_range(&self, mode: VisualMode, first: usize, last: usize) -> Range<usize> {
+        let text = self.text();
+        match mode {
+            VisualMode::Line if last < text.lenlines() => text.line_start(first)..text.line_start(last + 1),
+            VisualMode::Line => text.line_start(first)..text.len(),
+            _ => {
+                let sel = self.selection();
+                sel.start()..text.next_grapheme_boundary(sel.end())
+            },
+        }
+    }
+
+    /// where the cursor goes after yanking or deleting the selection
+    fn visual_start(&self, mode: VisualMode) -> usize {
+        if mode == VisualMode::Block {
+            let (first, _) = self.visual_lines();
+            let (left, _) = self.block_columns();
+            self.text().byte_at_column(first, left, self.tab_width)
+        } else {
+            self.selection().start()
+        }
+    }
+
+    fn visual_text(&self, mode: VisualMode) -> Rope {
+        let (first, last) = self.visual_lines();
+        let text = self.text();
+        match mode {
+            VisualMode::Char => text.char_slice(self.visual_range(mode, first, last)),
+            VisualMode::Line => {
+                let lines = text.char_slice(self.visual_range(mode, first, last));
+                if lines.chars_before(lines.len()).next().map(|(_, ch)| ch) == Some('\n') {
+                    lines
+                } else {
+                    Rope::concat(&lines, &Rope::from("\n"))
+                }
+            },
+            VisualMode::Block => {
+                let lines: Vec<_> = self.block_parts().into_iter()
+                    .map(|part| part.map_or(String::new(), |part| part.text))
+                    .collect();
+                Rope::from(&*lines.join("\n"))
+            },
+        }
+    }
+
+    /// the part of each line of the selection inside of the block
+    fn block_parts(&self) -> Vec<Option<BlockPart>> {
+        let (first, last) = self.visual_lines();
+        let (left, right) = self.block_columns();
+        (first..=last)
+            .map(|lnum| block_part(self.text(), lnum, left, right, self.tab_width))
+            .collect()
+    }
+
+    /// the bytes at display column `col` of each of `lines`, splitting the
+    /// tabs that cover it into spaces, as one undo step.
+    pub(crate) fn cursors_at_column(&mut self, lines: &[usize], col: usize) -> Vec<usize> {
+        self.begin_group();
+        for &lnum in lines.iter().rev() {
+            let idx = self.text().byte_at_column(lnum, col, self.tab_width);
+            let start = self.text().column_at(idx, self.tab_width);
+            if start < col && self.text().chars_from(idx).next().map(|(_, ch)| ch) == Some('\t') {
+                let width = char_width('\t', start, self.tab_width);
+                self.edit(idx, idx + 1, &spaces(width), idx);
+            }
+        }
+        self.end_group();
+        lines.iter().map(|&lnum| self.text().byte_at_column(lnum, col, self.tab_width)).collect()
+    }
+
+    /// leaves a cursor at each of `idxs`, the first being the primary one
+    fn set_cursors(&mut self, idxs: Vec<usize>) {
+        if !idxs.is_empty() {
+            self.set_selections(idxs.into_iter().map(Selection::cursor).collect(), 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Buffer;
+    use crate::rope::Rope;
+    use crate::boundary::WordKind;
+    use crate::visual::VisualMode;
+
+    fn visual(text: &str, cursor: usize, mode: VisualMode) -> Buffer {
+        let mut b = Buffer::from(Rope::from(text));
+        b.set_cursor(cursor);
+        b.start_visual(mode);
+        b
+    }
+
+    fn cursors(b: &Buffer) -> Vec<usize> {
+        b.selections().0.iter().map(|s| s.head).collect()
+    }
+
+    #[test]
+    fn test_charwise() {
+        let mut b = visual("hello world\nfoo", 2, VisualMode::Char);
+        b.move_word_forward(WordKind::Word);
+        assert_eq!(b.visual_yank().unwrap(), Rope::from("llo w"));
+        assert_eq!(b.visual_mode(), None);
+        assert_eq!(b.cursor(), 2);
+        assert!(b.visual_yank().is_none());
+
+        b.start_visual(VisualMode::Char);
+        b.move_right();
+        b.move_right();
+        assert_eq!(b.visual_delete().unwrap(), Rope::from("llo"));
+        assert_eq!(*b.text(), *"he world\nfoo");
+        b.undo();
+        assert_eq!(*b.text(), *"hello world\nfoo");
+
+        // selecting backwards includes the character under the anchor
+        let mut b = visual("abcdef", 4, VisualMode::Char);
+        b.move_left();
+        b.move_left();
+        assert_eq!(b.visual_change().unwrap(), Rope::from("cde"));
+        assert_eq!(*b.text(), *"abf");
+        assert_eq!(b.cursor(), 2);
+
+        let mut b = visual("abcdef", 1, VisualMode::Char);
+        b.move_right();
+        assert!(b.visual_append());
+        assert_eq!(b.cursor(), 3);
+    }
+
+    #[test]
+    fn test_linewise() {
+        let mut b = visual("a\n  b\nc\n", 3, VisualMode::Line);
+        assert_eq!(b.visual_yank().unwrap(), Rope::from("  b\n"));
+
+        b.start_visual(VisualMode::Line);
+        b.move_down();
+        assert_eq!(b.visual_delete().unwrap(), Rope::from("  b\nc\n"));
+        assert_eq!(*b.text(), *"a\n");
+        assert_eq!(b.cursor(), 2);
+        b.undo();
+
+        // the last line has no newline of its own to delete
+        let mut b = visual("a\nb", 2, VisualMode::Line);
+        assert_eq!(b.visual_delete().unwrap(), Rope::from("b\n"));
+        assert_eq!(*b.text(), *"a");
+        assert_eq!(b.cursor(), 0);
+
+        let mut b = visual("x\n  y\nz\nw", 4, VisualMode::Line);
+        b.move_down();
+        assert_eq!(b.visual_change().unwrap(), Rope::from("  y\nz\n"));
+        assert_eq!(*b.text(), *"x\n  \nw");
+        assert_eq!(b.cursor(), 4);
+
+        let mut b = visual("x\n  y\nz", 4, VisualMode::Line);
+        assert!(b.visual_insert());
+        assert_eq!(b.cursor(), 4);
+        b.start_visual(VisualMode::Line);
+        b.move_down();
+        assert!(b.visual_append());
+        assert_eq!(b.cursor(), 7);
+    }
+
+    #[test]
+    fn test_blockwise() {
+        let text = "abcd\nab\nabcdef\n";
+        let select = || {
+            let mut b = visual(text, 1, VisualMode::Block);
+            b.move_down();
+            b.move_down();
+            b.move_right();
+            b.move_right();
+            b
+        };
+
+        let mut b = select();
+        assert_eq!(b.visual_yank().unwrap(), Rope::from("bcd\nb\nbcd"));
+        assert_eq!(b.cursor(), 1);
+
+        let mut b = select();
+        assert_eq!(b.visual_delete().unwrap(), Rope::from("bcd\nb\nbcd"));
+        assert_eq!(*b.text(), *"a\na\naef\n");
+        assert_eq!(b.cursor(), 1);
+        b.undo();
+        assert_eq!(*b.text(), *text);
+
+        let mut b = select();
+        assert!(b.visual_insert());
+        b.insert_at_cursor("X");
+        assert_eq!(*b.text(), *"aXbcd\naXb\naXbcdef\n");
+
+        // short lines are padded out to the block
+        let mut b = select();
+        assert!(b.visual_append());
+        b.insert_at_cursor("|");
+        assert_eq!(*b.text(), *"abcd|\nab  |\nabcd|ef\n");
+        b.undo();
+        assert_eq!(*b.text(), *text);
+
+        let mut b = select();
+        b.move_line_end();
+        assert!(b.visual_append());
+        b.insert_at_cursor("|");
+        assert_eq!(*b.text(), *"abcd|\nab|\nabcdef|\n");
+
+        let mut b = select();
+        b.visual_change();
+        assert_eq!(cursors(&b), vec![1, 3, 5]);
+        b.insert_at_cursor("Z");
+        assert_eq!(*b.text(), *"aZ\naZ\naZef\n");
+        b.undo();
+        assert_eq!(*b.text(), *text);
+
+        // lines that don't reach the block are left alone
+        let mut b = visual("abcd\n\nabcd", 2, VisualMode::Block);
+        b.move_down();
+        b.move_down();
+        assert_eq!(b.visual_yank().unwrap(), Rope::from("c\n\nc"));
+        b.start_visual(VisualMode::Block);
+        b.move_down();
+        b.move_down();
+        assert!(b.visual_insert());
+        assert_eq!(cursors(&b), vec![2, 8]);
+    }
+
+    #[test]
+    fn test_blockwise_tabs() {
+        let text = "abcdef\na\tb\nabcdef";
+        let select = || {
+            let mut b = Buffer::from(Rope::from(text));
+            b.set_tab_width(4);
+            b.set_cursor(2);
+            b.start_visual(VisualMode::Block);
+            b.move_down();
+            b.move_down();
+            b
+        };
+
+        let mut b = select();
+        assert_eq!(b.visual_yank().unwrap(), Rope::from("c\n \nc"));
+        let mut b = select();
+        b.visual_delete();
+        assert_eq!(*b.text(), *"abdef\na  b\nabdef");
+
+        let mut b = select();
+        b.visual_insert();
+        b.insert_at_cursor("X");
+        assert_eq!(*b.text(), *"abXcdef\na X  b\nabXcdef");
+        b.undo();
+        assert_eq!(*b.text(), *text);
+
+        let mut b = select();
+        b.visual_append();
+        b.insert_at_cursor("X");
+        assert_eq!(*b.text(), *"abcXdef\na  X b\nabcXdef");
+    }
+}
@@ -0,0 +1,362 @@
+//! soft wrapping of a rope into visual rows that fit a viewport.
+use std::iter;
+use std::ops::Range;
+use crate::column::char_width;
+use crate::rope::Rope;
+
+/// where long lines may be broken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    /// break before any character that does not fit
+    Char,
+    /// break after the last whitespace that fits, or like `Char` if there is
+    /// none. whitespace at the end of a row may hang past the width.
+    Word,
+}
+
+/// a visual position: a row counted from the top of the text across all
+/// wrapped lines, and a display column within that row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VisualPos {
+    pub row: usize,
+    pub col: usize,
+}
+
+/// Layout wraps the lines of a rope to a viewport width.
+///
+/// lines are only wrapped when they are first needed, and are remembered
+/// until an edit touches them, so moving through a large file only does the
+/// work for the lines that are looked at. the number of rows before each
+/// line is remembered the same way, up to the first line an edit touches.
+///
+/// # example
+/// ```
+/// use buffer::rope::Rope;
+/// use buffer::wrap::{Layout, WrapMode, VisualPos};
+///
+/// let mut layout = Layout::new(Rope::from("hello wide world\n"), 10, WrapMode::Word, 8);
+/// assert_eq!(layout.row_range(1), Some(11..16));
+/// assert_eq!(layout.visual_pos(13), VisualPos { row: 1, col: 2 });
+/// assert_eq!(layout.byte_at(VisualPos { row: 1, col: 2 }), 13);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Layout {
+    rope:      Rope,
+    width:     usize,
+    mode:      WrapMode,
+    tab_width: usize,
+    /// for each line, the offsets from the start of the line where each row
+    /// after the first begins, once the line has been wrapped.
+    breaks:    Vec<Option<Vec<usize>>>,
+    /// the number of rows before each line, for as many lines as are known.
+    /// it always starts with the 0 rows before the first line.
+    rows_before: Vec<usize>,
+}
+
+impl Layout {
+    /// creates a layout of `rope` for a viewport `width` columns wide.
+    pub fn new(rope: Rope, width: usize, mode: WrapMode, tab_width: usize) -> Self {
+        let breaks = vec![None; rope.lenlines() + 1];
+        Layout {
+            rope,
+            width: width.max(1),
+            mode,
+            tab_width,
+            breaks,
+            rows_before: vec![0],
+        }
+    }
+
+    /// the rope being laid out
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    /// changes the viewport width, which rewraps every line.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width.max(1);
+        self.invalidate();
+    }
+
+    /// changes the wrap mode, which rewraps every line.
+    pub fn set_mode(&mut self, mode: WrapMode) {
+        self.mode = mode;
+        self.invalidate();
+    }
+
+    /// lays out a completely different rope.
+    pub fn set_rope(&mut self, rope: Rope) {
+        self.rope = rope;
+        self.invalidate();
+    }
+
+    fn invalidate(&mut self) {
+        self.breaks = vec![None; self.rope.lenlines() + 1];
+        self.rows_before = vec![0];
+    }
+
+    /// updates the layout after an edit that replaced the bytes
+    /// `start..old_end` of the current rope, giving `rope`, where the new text
+    /// ends at `new_end`. only the lines touched by the edit are rewrapped.
+    pub fn edit(&mut self, rope: Rope, start: usize, old_end: usize, new_end: usize) {
+        let first = self.rope.line_at(start);
+        let old_last = self.rope.line_at(old_end);
+        let new_last = rope.line_at(new_end);
+        self.breaks.splice(first..=old_last, iter::repeat_n(None, new_last - first + 1));
+        self.rows_before.truncate(first + 1);
+        self.rope = rope;
+    }
+
+    /// the number of visual rows that line `lnum` is wrapped into.
+    pub fn rows_in_line(&mut self, lnum: usize) -> usize {
+        self.line_breaks(lnum).len() + 1
+    }
+
+    /// the number of visual rows in the whole text.
+    pub fn rows(&mut self) -> usize {
+        self.rows_before(self.breaks.len())
+    }
+
+    /// the bytes shown on visual `row`, not including a newline.
+    /// returns None for rows past the end of the text.
+    pub fn row_range(&mut self, row: usize) -> Option<Range<usize>> {
+        let (lnum, r) = self.find_row(row)?;
+        let line = self.rope.line_start(lnum);
+        let breaks = self.line_breaks(lnum);
+        let start = if r == 0 { 0 } else { breaks[r - 1] };
+        let end = match breaks.get(r) {
+            Some(&end) => line + end,
+            None => self.rope.line_end(lnum),
+        };
+        Some(line + start..end)
+    }
+
+    /// the visual position of byte `idx`. a byte where a line is wrapped is
+    /// at the start of the lower row.
+    pub fn visual_pos(&mut self, idx: usize) -> VisualPos {
+        let idx = idx.min(self.rope.len());
+        let lnum = self.rope.line_at(idx);
+        let line = self.rope.line_start(lnum);
+        let rel = idx - line;
+
+        let breaks = self.line_breaks(lnum);
+        let r = breaks.partition_point(|&b| b <= rel);
+        let start = line + if r == 0 { 0 } else { breaks[r - 1] };
+
+        let tab_width = self.tab_width;
+        let col = self.rope.chars_from(start)
+            .take_while(|&(i, _)| i < idx)
+            .fold(0, |col, (_, ch)| col + char_width(ch, col, tab_width));
+        let row = self.rows_before(lnum) + r;
+        VisualPos { row, col }
+    }
+
+    /// the byte of the character at a visual position. a column in the middle
+    /// of a wide character gives the start of that character. columns past
+    /// the end of a row give its last character, or the end of the line for
+    /// the last row of a line. rows past the end give the end of the text.
+    pub fn byte_at(&mut self, pos: VisualPos) -> usize {
+        let range = match self.row_range(pos.row) {
+            Some(range) => range,
+            None => return self.rope.len(),
+        };
+        let wrapped = self.rope.chars_from(range.end).next().is_some_and(|(_, ch)| ch != '\n');
+
+        let mut col = 0;
+        let mut last = range.start;
+        for (i, ch) in self.rope.chars_from(range.start).take_while(|&(i, _)| i < range.end) {
+            let width = char_width(ch, col, self.tab_width);
+            if col + width > pos.col {
+                return i;
+            }
+            col += width;
+            last = i;
+        }
+        if wrapped { last } else { range.end }
+    }
+
+    /// finds the line containing visual `row`, and the row within that line.
+    fn find_row(&mut self, row: usize) -> Option<(usize, usize)> {
+        while self.rows_before.len() <= self.breaks.len() && self.rows_before.last() <= Some(&row) {
+            self.rows_before(self.rows_before.len());
+        }
+        let lnum = self.rows_before.partition_point(|&r| r <= row) - 1;
+        if lnum >= self.breaks.len() {
+            return None;
+        }
+        Some((lnum, row - self.rows_before[lnum]))
+    }
+
+    /// the number of rows before line `lnum`, counting the ones that are not
+    /// known yet.
+    fn rows_before(&mut self, lnum: usize) -> usize {
+        while self.rows_before.len() <= lnum {
+            let l = self.rows_before.len() - 1;
+            let rows = self.rows_before[l] + self.rows_in_line(l);
+            self.rows_before.push(rows);
+        }
+        self.rows_before[lnum]
+    }
+
+    fn line_breaks(&mut self, lnum: usize) -> &[usize] {
+        if lnum >= self.breaks.len() {
+            return &[];
+        }
+        if self.breaks[lnum].is_none() {
+            let start = self.rope.line_start(lnum);
+            let text: String = self.rope.char_slice(start..self.rope.line_end(lnum))
+                .str_iter()
+                .collect();
+            self.breaks[lnum] = Some(wrap_line(&text, self.width, self.mode, self.tab_width));
+        }
+        self.breaks[lnum].as_deref().unwrap_or(&[])
+    }
+}
+
+/// finds the offsets in `line` where each row after the first should start.
+fn wrap_line(line: &str, width: usize, mode: WrapMode, tab_width: usize) -> Vec<usize> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut breaks = vec![];
+    let mut row_start = 0;
+    let mut col = 0;
+    // where the current row could be broken in word mode
+    let mut after_space = None;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (_, ch) = chars[i];
+        let width_here = char_width(ch, col, tab_width);
+        let hangs = mode == WrapMode::Word && ch.is_whitespace();
+
+        if col + width_here > width && col > 0 && !hangs {
+            let brk = match after_space {
+                Some(s) if mode == WrapMode::Word && s > row_start => s,
+                _ => i,
+            };
+            breaks.push(chars[brk].0);
+            row_start = brk;
+            i = brk;
+            col = 0;
+            after_space = None;
+            continue;
+        }
+
+        col += width_here;
+        if ch.is_whitespace() {
+            after_space = Some(i + 1);
+        }
+        i += 1;
+    }
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rope::Rope;
+    use crate::wrap::{Layout, WrapMode, VisualPos, wrap_line};
+
+    #[test]
+    fn test_wrap_char() {
+        assert_eq!(wrap_line("abcdefghij", 4, WrapMode::Char, 8), vec![4, 8]);
+        assert_eq!(wrap_line("abcd", 4, WrapMode::Char, 8), Vec::<usize>::new());
+        assert_eq!(wrap_line("", 4, WrapMode::Char, 8), Vec::<usize>::new());
+        // wide characters that do not fit move to the next row
+        assert_eq!(wrap_line("ab日本", 3, WrapMode::Char, 8), vec![2, 5]);
+        // combining marks stay with their letter
+        assert_eq!(wrap_line("abce\u{301}f", 4, WrapMode::Char, 8), vec![6]);
+        // tabs are measured from the start of their row
+        assert_eq!(wrap_line("a\tbc\td", 4, WrapMode::Char, 4), vec![2, 5]);
+        // characters wider than the viewport still get a row
+        assert_eq!(wrap_line("日本", 1, WrapMode::Char, 8), vec![3]);
+    }
+
+    #[test]
+    fn test_wrap_word() {
+        assert_eq!(wrap_line("the quick brown fox", 10, WrapMode::Word, 8), vec![10]);
+        assert_eq!(wrap_line("the quick brown fox", 9, WrapMode::Word, 8), vec![10]);
+        assert_eq!(wrap_line("the quick brown fox", 8, WrapMode::Word, 8), vec![4, 10, 16]);
+        // whitespace may hang past the end of a row
+        assert_eq!(wrap_line("abcd   efg", 4, WrapMode::Word, 8), vec![7]);
+        // words longer than a row are broken anywhere
+        assert_eq!(wrap_line("a abcdefgh", 4, WrapMode::Word, 8), vec![2, 6]);
+    }
+
+    #[test]
+    fn test_row_range() {
+        let rope = Rope::concat(&Rope::from("abcdef\n"), &Rope::from("\nabc"));
+        let mut layout = Layout::new(rope, 4, WrapMode::Char, 8);
+        assert_eq!(layout.rows(), 4);
+        assert_eq!(layout.rows_in_line(0), 2);
+        assert_eq!(layout.row_range(0), Some(0..4));
+        assert_eq!(layout.row_range(1), Some(4..6));
+        assert_eq!(layout.row_range(2), Some(7..7));
+        assert_eq!(layout.row_range(3), Some(8..11));
+        assert_eq!(layout.row_range(4), None);
+    }
+
+    #[test]
+    fn test_visual_pos() {
+        let rope = Rope::from("ab日本cd\nxyz");
+        let mut layout = Layout::new(rope, 4, WrapMode::Char, 8);
+        assert_eq!(layout.visual_pos(0), VisualPos { row: 0, col: 0 });
+        assert_eq!(layout.visual_pos(2), VisualPos { row: 0, col: 2 });
+        assert_eq!(layout.visual_pos(5), VisualPos { row: 1, col: 0 });
+        assert_eq!(layout.visual_pos(8), VisualPos { row: 1, col: 2 });
+        assert_eq!(layout.visual_pos(10), VisualPos { row: 1, col: 4 });
+        assert_eq!(layout.visual_pos(12), VisualPos { row: 2, col: 1 });
+        assert_eq!(layout.visual_pos(100), VisualPos { row: 2, col: 3 });
+    }
+
+    #[test]
+    fn test_byte_at() {
+        let rope = Rope::from("ab日本cd\nxyz");
+        let mut layout = Layout::new(rope, 4, WrapMode::Char, 8);
+        assert_eq!(layout.byte_at(VisualPos { row: 0, col: 1 }), 1);
+        assert_eq!(layout.byte_at(VisualPos { row: 0, col: 3 }), 2);
+        // past the end of a wrapped row is its last character
+        assert_eq!(layout.byte_at(VisualPos { row: 0, col: 9 }), 2);
+        assert_eq!(layout.byte_at(VisualPos { row: 1, col: 1 }), 5);
+        assert_eq!(layout.byte_at(VisualPos { row: 1, col: 3 }), 9);
+        assert_eq!(layout.byte_at(VisualPos { row: 1, col: 9 }), 10);
+        assert_eq!(layout.byte_at(VisualPos { row: 2, col: 2 }), 13);
+        assert_eq!(layout.byte_at(VisualPos { row: 2, col: 9 }), 14);
+        assert_eq!(layout.byte_at(VisualPos { row: 7, col: 0 }), 14);
+    }
+
+    #[test]
+    fn test_edit() {
+        let rope = Rope::from("aaaaaa\nbbbbbb\ncccccc\n");
+        let mut layout = Layout::new(rope.clone(), 4, WrapMode::Char, 8);
+        assert_eq!(layout.rows(), 7);
+
+        // join the first two lines and split the last one
+        let edited = rope.delete(6..7).insert(17, Rope::from("\n\n"));
+        layout.edit(rope.delete(6..7), 6, 7, 6);
+        layout.edit(edited.clone(), 17, 17, 19);
+        let mut fresh = Layout::new(edited, 4, WrapMode::Char, 8);
+        assert_eq!(layout.rows(), fresh.rows());
+        for row in 0..fresh.rows() + 1 {
+            assert_eq!(layout.row_range(row), fresh.row_range(row));
+        }
+
+        // rows after an edit are counted again
+        let longer = fresh.rope().insert(0, Rope::from("aaaa"));
+        layout.edit(longer.clone(), 0, 0, 4);
+        let mut fresh = Layout::new(longer.clone(), 4, WrapMode::Char, 8);
+        for idx in 0..=longer.len() {
+            assert_eq!(layout.visual_pos(idx), fresh.visual_pos(idx));
+        }
+        assert_eq!(layout.rows(), fresh.rows());
+    }
+
+    #[test]
+    fn test_set_width() {
+        let mut layout = Layout::new(Rope::from("the quick brown fox"), 8, WrapMode::Word, 8);
+        assert_eq!(layout.rows(), 4);
+        layout.set_width(20);
+        assert_eq!(layout.rows(), 1);
+        layout.set_mode(WrapMode::Char);
+        layout.set_width(5);
+        assert_eq!(layout.row_range(1), Some(5..10));
+    }
+}